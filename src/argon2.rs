@@ -0,0 +1,417 @@
+//! Argon2id password hashing (RFC 9106), producing PHC strings.
+
+use rand::{rngs::OsRng, Rng};
+
+const VERSION: u32 = 0x13;
+const ARGON2ID: u32 = 2;
+const SYNC_POINTS: usize = 4;
+const BLOCK_WORDS: usize = 128;
+const TAG_LENGTH: usize = 32;
+
+type Block = [u64; BLOCK_WORDS];
+
+/// Hashes `password` with a random salt, producing an `$argon2id$` PHC string.
+///
+/// `memory` is given in KiB, and must be at least 8 times `parallelism`.
+pub fn hash(password: &[u8], memory: u32, iterations: u32, parallelism: u32) -> String {
+  let salt: [u8; 16] = OsRng.gen();
+  let tag = argon2id(password, &salt, &[], &[], memory, iterations, parallelism);
+
+  format!(
+    "$argon2id$v={VERSION}$m={memory},t={iterations},p={parallelism}${}${}",
+    base64::encode_config(salt, base64::STANDARD_NO_PAD),
+    base64::encode_config(tag, base64::STANDARD_NO_PAD),
+  )
+}
+
+/// Computes the argon2id tag of `password`, optionally keyed with a `secret` and bound to `associated_data`.
+pub(crate) fn argon2id(
+  password: &[u8],
+  salt: &[u8],
+  secret: &[u8],
+  associated_data: &[u8],
+  memory: u32,
+  iterations: u32,
+  parallelism: u32,
+) -> Vec<u8> {
+  assert!(parallelism >= 1 && memory >= 8 * parallelism && iterations >= 1);
+
+  let mut input = Vec::new();
+  for value in [parallelism, TAG_LENGTH as u32, memory, iterations, VERSION, ARGON2ID] {
+    input.extend_from_slice(&value.to_le_bytes());
+  }
+
+  for value in [password, salt, secret, associated_data] {
+    input.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    input.extend_from_slice(value);
+  }
+
+  let h0 = blake2b(64, &input);
+
+  let lanes = parallelism as usize;
+  let segment_length = memory as usize / (SYNC_POINTS * lanes);
+  let lane_length = segment_length * SYNC_POINTS;
+  let mut memory = vec![[0u64; BLOCK_WORDS]; lane_length * lanes];
+
+  for lane in 0..lanes {
+    for column in 0..2 {
+      let mut seed = h0.clone();
+      seed.extend_from_slice(&(column as u32).to_le_bytes());
+      seed.extend_from_slice(&(lane as u32).to_le_bytes());
+      memory[lane * lane_length + column] = block_from_bytes(&blake2b_long(1024, &seed));
+    }
+  }
+
+  let instance = Instance {
+    lanes,
+    segment_length,
+    lane_length,
+    iterations: iterations as usize,
+  };
+
+  for pass in 0..instance.iterations {
+    for slice in 0..SYNC_POINTS {
+      for lane in 0..lanes {
+        instance.fill_segment(&mut memory, pass, slice, lane);
+      }
+    }
+  }
+
+  let mut last = memory[lane_length - 1];
+  for lane in 1..lanes {
+    xor_block(&mut last, &memory[lane * lane_length + lane_length - 1]);
+  }
+
+  let bytes = last.iter().flat_map(|w| w.to_le_bytes()).collect::<Vec<_>>();
+  blake2b_long(TAG_LENGTH, &bytes)
+}
+
+struct Instance {
+  lanes: usize,
+  segment_length: usize,
+  lane_length: usize,
+  iterations: usize,
+}
+
+impl Instance {
+  fn fill_segment(&self, memory: &mut [Block], pass: usize, slice: usize, lane: usize) {
+    // argon2id uses data-independent addressing for the first half of the first pass
+    let data_independent = pass == 0 && slice < SYNC_POINTS / 2;
+
+    let zero = [0u64; BLOCK_WORDS];
+    let mut input = [0u64; BLOCK_WORDS];
+    let mut addresses = [0u64; BLOCK_WORDS];
+    input[..6].copy_from_slice(&[
+      pass as u64,
+      lane as u64,
+      slice as u64,
+      (self.lane_length * self.lanes) as u64,
+      self.iterations as u64,
+      ARGON2ID as u64,
+    ]);
+
+    let next_addresses = |input: &mut Block, addresses: &mut Block| {
+      input[6] += 1;
+      *addresses = compress(&zero, input);
+      *addresses = compress(&zero, addresses);
+    };
+
+    let starting_index = match (pass, slice) {
+      (0, 0) => {
+        if data_independent {
+          next_addresses(&mut input, &mut addresses);
+        }
+
+        2
+      }
+      _ => 0,
+    };
+
+    for index in starting_index..self.segment_length {
+      let column = slice * self.segment_length + index;
+      let current = lane * self.lane_length + column;
+      let previous = match column {
+        0 => current + self.lane_length - 1,
+        _ => current - 1,
+      };
+
+      let pseudo_random = match data_independent {
+        true => {
+          if index % BLOCK_WORDS == 0 {
+            next_addresses(&mut input, &mut addresses);
+          }
+
+          addresses[index % BLOCK_WORDS]
+        }
+        false => memory[previous][0],
+      };
+
+      let reference_lane = match (pass, slice) {
+        (0, 0) => lane,
+        _ => ((pseudo_random >> 32) % self.lanes as u64) as usize,
+      };
+
+      let reference = self.reference_index(pass, slice, index, pseudo_random & 0xffff_ffff, reference_lane == lane);
+      let mut block = compress(
+        &memory[previous],
+        &memory[reference_lane * self.lane_length + reference],
+      );
+      if pass > 0 {
+        xor_block(&mut block, &memory[current]);
+      }
+
+      memory[current] = block;
+    }
+  }
+
+  fn reference_index(&self, pass: usize, slice: usize, index: usize, pseudo_random: u64, same_lane: bool) -> usize {
+    let finished = match pass {
+      0 => slice * self.segment_length,
+      _ => self.lane_length - self.segment_length,
+    };
+
+    let area = match (same_lane, index) {
+      (true, _) => finished + index - 1,
+      (false, 0) => finished - 1,
+      (false, _) => finished,
+    } as u64;
+
+    let relative = (pseudo_random * pseudo_random) >> 32;
+    let relative = area - 1 - ((area * relative) >> 32);
+    let start = match (pass, slice) {
+      (0, _) | (_, 3) => 0,
+      _ => (slice + 1) * self.segment_length,
+    };
+
+    (start + relative as usize) % self.lane_length
+  }
+}
+
+fn block_from_bytes(bytes: &[u8]) -> Block {
+  let mut block = [0u64; BLOCK_WORDS];
+  for (word, chunk) in block.iter_mut().zip(bytes.chunks_exact(8)) {
+    *word = u64::from_le_bytes(chunk.try_into().unwrap());
+  }
+
+  block
+}
+
+fn xor_block(target: &mut Block, other: &Block) {
+  for (a, b) in target.iter_mut().zip(other) {
+    *a ^= b;
+  }
+}
+
+/// The argon2 compression function G.
+fn compress(x: &Block, y: &Block) -> Block {
+  let mut r = *x;
+  xor_block(&mut r, y);
+
+  let mut z = r;
+  for row in 0..8 {
+    let mut indices = [0; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+      *index = row * 16 + i;
+    }
+
+    permute(&mut z, indices);
+  }
+
+  for column in 0..8 {
+    let mut indices = [0; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+      *index = 2 * column + (i / 2) * 16 + i % 2;
+    }
+
+    permute(&mut z, indices);
+  }
+
+  xor_block(&mut z, &r);
+  z
+}
+
+fn permute(v: &mut Block, i: [usize; 16]) {
+  for [a, b, c, d] in [
+    [0, 4, 8, 12],
+    [1, 5, 9, 13],
+    [2, 6, 10, 14],
+    [3, 7, 11, 15],
+    [0, 5, 10, 15],
+    [1, 6, 11, 12],
+    [2, 7, 8, 13],
+    [3, 4, 9, 14],
+  ] {
+    let (a, b, c, d) = (i[a], i[b], i[c], i[d]);
+    let mul = |x: u64, y: u64| 2u64.wrapping_mul(x & 0xffff_ffff).wrapping_mul(y & 0xffff_ffff);
+
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(mul(v[a], v[b]));
+    v[d] = (v[d] ^ v[a]).rotate_right(32);
+    v[c] = v[c].wrapping_add(v[d]).wrapping_add(mul(v[c], v[d]));
+    v[b] = (v[b] ^ v[c]).rotate_right(24);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(mul(v[a], v[b]));
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]).wrapping_add(mul(v[c], v[d]));
+    v[b] = (v[b] ^ v[c]).rotate_right(63);
+  }
+}
+
+/// The variable-length hash function H'.
+fn blake2b_long(length: usize, input: &[u8]) -> Vec<u8> {
+  let mut data = (length as u32).to_le_bytes().to_vec();
+  data.extend_from_slice(input);
+
+  if length <= 64 {
+    return blake2b(length, &data);
+  }
+
+  // the first 32 bytes of a chain of 64 byte hashes, with the last hash sized to fill the remainder
+  let chained = length.div_ceil(32) - 2;
+  let mut out = Vec::with_capacity(length);
+  let mut v = blake2b(64, &data);
+  for _ in 1..chained {
+    out.extend_from_slice(&v[..32]);
+    v = blake2b(64, &v);
+  }
+
+  out.extend_from_slice(&v[..32]);
+  out.extend_from_slice(&blake2b(length - 32 * chained, &v));
+  out
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+  0x6a09e667f3bcc908,
+  0xbb67ae8584caa73b,
+  0x3c6ef372fe94f82b,
+  0xa54ff53a5f1d36f1,
+  0x510e527fade682d1,
+  0x9b05688c2b3e6c1f,
+  0x1f83d9abfb41bd6b,
+  0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+  [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+  [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+  [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+  [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+  [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+  [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+  [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+  [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+  [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+  [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// Unkeyed BLAKE2b (RFC 7693) with an output of `length` bytes.
+fn blake2b(length: usize, data: &[u8]) -> Vec<u8> {
+  assert!((1..=64).contains(&length));
+
+  let mut h = BLAKE2B_IV;
+  h[0] ^= 0x0101_0000 ^ length as u64;
+
+  let blocks = data.len().div_ceil(128).max(1);
+  for i in 0..blocks {
+    let start = i * 128;
+    let end = data.len().min(start + 128);
+    let mut block = [0u8; 128];
+    block[..end - start].copy_from_slice(&data[start..end]);
+
+    let last = i == blocks - 1;
+    blake2b_compress(&mut h, &block, end as u128, last);
+  }
+
+  h.iter().flat_map(|w| w.to_le_bytes()).take(length).collect()
+}
+
+fn blake2b_compress(h: &mut [u64; 8], block: &[u8; 128], counter: u128, last: bool) {
+  let mut m = [0u64; 16];
+  for (word, chunk) in m.iter_mut().zip(block.chunks_exact(8)) {
+    *word = u64::from_le_bytes(chunk.try_into().unwrap());
+  }
+
+  let mut v = [0u64; 16];
+  v[..8].copy_from_slice(h);
+  v[8..].copy_from_slice(&BLAKE2B_IV);
+  v[12] ^= counter as u64;
+  v[13] ^= (counter >> 64) as u64;
+  if last {
+    v[14] = !v[14];
+  }
+
+  for round in 0..12 {
+    let s = &BLAKE2B_SIGMA[round % 10];
+    for (i, [a, b, c, d]) in [
+      [0, 4, 8, 12],
+      [1, 5, 9, 13],
+      [2, 6, 10, 14],
+      [3, 7, 11, 15],
+      [0, 5, 10, 15],
+      [1, 6, 11, 12],
+      [2, 7, 8, 13],
+      [3, 4, 9, 14],
+    ]
+    .into_iter()
+    .enumerate()
+    {
+      let (x, y) = (m[s[2 * i]], m[s[2 * i + 1]]);
+      v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+      v[d] = (v[d] ^ v[a]).rotate_right(32);
+      v[c] = v[c].wrapping_add(v[d]);
+      v[b] = (v[b] ^ v[c]).rotate_right(24);
+      v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+      v[d] = (v[d] ^ v[a]).rotate_right(16);
+      v[c] = v[c].wrapping_add(v[d]);
+      v[b] = (v[b] ^ v[c]).rotate_right(63);
+    }
+  }
+
+  for i in 0..8 {
+    h[i] ^= v[i] ^ v[i + 8];
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+  }
+
+  #[test]
+  fn rfc9106_argon2id_vector() {
+    // RFC 9106 section 5.3
+    let tag = argon2id(&[1; 32], &[2; 16], &[3; 8], &[4; 12], 32, 3, 4);
+    assert_eq!(
+      hex(&tag),
+      "0d640df58d78766c08c037a34a8b53c9d01ef0452d75b65eb52520e96b01e659"
+    );
+  }
+
+  #[test]
+  fn blake2b_512_abc_vector() {
+    // RFC 7693 appendix A
+    assert_eq!(
+      hex(&blake2b(64, b"abc")),
+      concat!(
+        "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1",
+        "7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+      )
+    );
+  }
+
+  #[test]
+  fn hash_is_a_phc_string() {
+    let hashed = hash(b"password", 64, 1, 2);
+    let fields: Vec<_> = hashed.split('$').collect();
+    assert_eq!(fields[..4], ["", "argon2id", "v=19", "m=64,t=1,p=2"]);
+    assert_eq!(
+      base64::decode_config(fields[4], base64::STANDARD_NO_PAD).unwrap().len(),
+      16
+    );
+    assert_eq!(
+      base64::decode_config(fields[5], base64::STANDARD_NO_PAD).unwrap().len(),
+      TAG_LENGTH
+    );
+  }
+}
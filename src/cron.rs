@@ -0,0 +1,265 @@
+//! Cron expressions (`minute hour day-of-month month day-of-week`), evaluated in UTC.
+
+use k8s_openapi::chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+
+const MONTHS: [&str; 12] = [
+  "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Number of days searched for the next time a schedule fires, which covers every day of the month and day of the week
+/// combination, including the 29th of February.
+const SEARCHED_DAYS: i64 = 8 * 366;
+
+/// Parsed cron expression, with the allowed values of every field as a set of bits.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Schedule {
+  minutes: u64,
+  hours: u32,
+  days: u32,
+  months: u16,
+  weekdays: u8,
+  // days are matched when either the day of the month or the day of the week matches, if both are restricted
+  any_day: bool,
+}
+
+impl Schedule {
+  /// Parses the five fields of `expression`, or one of the macros '@yearly', '@monthly', '@weekly', '@daily' and
+  /// '@hourly'. Fields are a comma separated list of '*', values, or ranges of values ('1-5'), optionally with a step
+  /// ('*/15' or '0-30/10'). Months and days of the week can also be given by their english three letter names, and
+  /// sunday can be both 0 and 7.
+  pub fn parse(expression: &str) -> Result<Self, String> {
+    let expression = match expression.trim() {
+      "@yearly" | "@annually" => "0 0 1 1 *",
+      "@monthly" => "0 0 1 * *",
+      "@weekly" => "0 0 * * 0",
+      "@daily" | "@midnight" => "0 0 * * *",
+      "@hourly" => "0 * * * *",
+      expression => expression,
+    };
+
+    let fields = expression.split_whitespace().collect::<Vec<_>>();
+    let [minutes, hours, days, months, weekdays] = fields[..] else {
+      return Err(format!("expected 5 fields, found {}", fields.len()));
+    };
+
+    // sunday is both 0 and 7
+    let weekday_bits = field(weekdays, "day of the week", 0, 7, &WEEKDAYS)?;
+    let schedule = Self {
+      minutes: field(minutes, "minute", 0, 59, &[])?,
+      hours: field(hours, "hour", 0, 23, &[])? as u32,
+      days: field(days, "day of the month", 1, 31, &[])? as u32,
+      months: field(months, "month", 1, 12, &MONTHS)? as u16,
+      weekdays: ((weekday_bits | weekday_bits >> 7) & 0x7f) as u8,
+      any_day: !days.starts_with('*') && !weekdays.starts_with('*'),
+    };
+
+    if schedule.next_after(Utc::now()).is_none() {
+      return Err("the schedule never fires".into());
+    }
+
+    Ok(schedule)
+  }
+
+  /// First time after `time` the schedule fires.
+  pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let start = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+    for offset in 0..SEARCHED_DAYS {
+      let date = start.date() + Duration::days(offset);
+      if !self.matches_day(date.naive_utc()) {
+        continue;
+      }
+
+      // the first day starts at the time after `time`, the others at midnight
+      let from = match offset {
+        0 => (start.hour(), start.minute()),
+        _ => (0, 0),
+      };
+
+      let hours = (from.0..24).filter(|hour| self.hours & 1 << hour != 0);
+      for hour in hours {
+        let first_minute = if hour == from.0 { from.1 } else { 0 };
+        if let Some(minute) = (first_minute..60).find(|minute| self.minutes & 1 << minute != 0) {
+          return date.and_hms_opt(hour, minute, 0);
+        }
+      }
+    }
+
+    None
+  }
+
+  fn matches_day(&self, date: NaiveDate) -> bool {
+    if self.months & 1 << date.month() == 0 {
+      return false;
+    }
+
+    let day = self.days & 1 << date.day() != 0;
+    let weekday = self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0;
+    match self.any_day {
+      true => day || weekday,
+      false => day && weekday,
+    }
+  }
+}
+
+/// Parses the cron field `name` into a set of bits of the values it allows, between `min` and `max`. `names` are the
+/// names of the values from `min`.
+fn field(field: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+  let value = |value: &str| -> Result<u32, String> {
+    let named = names.iter().position(|n| n.eq_ignore_ascii_case(value));
+    let value = match named {
+      Some(index) => index as u32 + min,
+      None => value
+        .parse::<u32>()
+        .map_err(|_| format!("invalid {name} '{}'", value.escape_default()))?,
+    };
+
+    match value {
+      _ if (min..=max).contains(&value) => Ok(value),
+      _ => Err(format!("{name} {value} is not between {min} and {max}")),
+    }
+  };
+
+  let mut bits = 0;
+  for item in field.split(',') {
+    let (range, step) = match item.split_once('/') {
+      Some((range, step)) => match step.parse::<u32>() {
+        Ok(step) if step > 0 => (range, step),
+        _ => return Err(format!("invalid step '{}' of the {name}", step.escape_default())),
+      },
+      None => (item, 1),
+    };
+
+    let (first, last) = match range.split_once('-') {
+      _ if range == "*" => (min, max),
+      Some((first, last)) => (value(first)?, value(last)?),
+      // a single value with a step runs to the end of the range, like '5/15'
+      None if item.contains('/') => (value(range)?, max),
+      None => (value(range)?, value(range)?),
+    };
+
+    if first > last {
+      return Err(format!("the {name} range {first}-{last} is reversed"));
+    }
+
+    for value in (first..=last).step_by(step as usize) {
+      bits |= 1 << value;
+    }
+  }
+
+  Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn utc(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+  }
+
+  fn next_after(expression: &str, time: &str) -> DateTime<Utc> {
+    Schedule::parse(expression).unwrap().next_after(utc(time)).unwrap()
+  }
+
+  #[test]
+  fn invalid_expressions_are_rejected() {
+    let invalid = [
+      "",
+      "* * * *",
+      "* * * * * *",
+      "60 * * * *",
+      "* 24 * * *",
+      "* * 0 * *",
+      "* * 32 * *",
+      "* * * 13 *",
+      "* * * * 8",
+      "*/0 * * * *",
+      "5-1 * * * *",
+      "x * * * *",
+      "* * * smarch *",
+      "@fortnightly",
+    ];
+    for expression in invalid {
+      assert!(Schedule::parse(expression).is_err(), "{expression}");
+    }
+  }
+
+  #[test]
+  fn schedules_that_never_fire_are_rejected() {
+    assert_eq!(Schedule::parse("0 0 30 feb *"), Err("the schedule never fires".into()));
+    assert_eq!(
+      Schedule::parse("0 0 31 apr,jun,sep,nov *"),
+      Err("the schedule never fires".into())
+    );
+    assert!(Schedule::parse("0 0 29 feb *").is_ok());
+  }
+
+  #[test]
+  fn next_time_is_strictly_after() {
+    assert_eq!(
+      next_after("30 2 * * *", "2024-05-01T02:30:00Z"),
+      utc("2024-05-02T02:30:00Z")
+    );
+    assert_eq!(
+      next_after("30 2 * * *", "2024-05-01T02:29:59Z"),
+      utc("2024-05-01T02:30:00Z")
+    );
+    assert_eq!(
+      next_after("*/15 * * * *", "2024-05-01T10:50:00Z"),
+      utc("2024-05-01T11:00:00Z")
+    );
+  }
+
+  #[test]
+  fn next_time_crosses_months_and_years() {
+    assert_eq!(
+      next_after("0 3 1 * *", "2024-01-31T12:00:00Z"),
+      utc("2024-02-01T03:00:00Z")
+    );
+    assert_eq!(
+      next_after("@yearly", "2024-12-31T23:59:30Z"),
+      utc("2025-01-01T00:00:00Z")
+    );
+    assert_eq!(
+      next_after("0 0 31 * *", "2024-04-01T00:00:00Z"),
+      utc("2024-05-31T00:00:00Z")
+    );
+    assert_eq!(
+      next_after("0 0 29 feb *", "2024-03-01T00:00:00Z"),
+      utc("2028-02-29T00:00:00Z")
+    );
+  }
+
+  #[test]
+  fn schedules_are_evaluated_in_utc() {
+    // the days daylight saving time starts and ends in europe and the us have the same hours in utc
+    for day in ["2024-03-10", "2024-03-31", "2024-10-27", "2024-11-03"] {
+      let expected = utc(&format!("{day}T02:30:00Z"));
+      assert_eq!(next_after("30 2 * * *", &format!("{day}T00:00:00Z")), expected);
+      assert_eq!(next_after("30 * * * *", &format!("{day}T01:59:00Z")), expected);
+    }
+  }
+
+  #[test]
+  fn days_match_either_the_day_of_the_month_or_the_day_of_the_week() {
+    // 2024-09-01 is a sunday, and the first friday is before the 13th
+    assert_eq!(
+      next_after("0 0 13 * fri", "2024-09-01T00:00:00Z"),
+      utc("2024-09-06T00:00:00Z")
+    );
+    assert_eq!(
+      next_after("0 0 13 * *", "2024-09-01T00:00:00Z"),
+      utc("2024-09-13T00:00:00Z")
+    );
+    assert_eq!(
+      next_after("0 0 * * 7", "2024-09-02T00:00:00Z"),
+      utc("2024-09-08T00:00:00Z")
+    );
+    assert_eq!(Schedule::parse("0 0 * * 7"), Schedule::parse("0 0 * * SUN"));
+    assert_eq!(
+      next_after("0 0 * jan mon-fri", "2024-12-01T00:00:00Z"),
+      utc("2025-01-01T00:00:00Z")
+    );
+  }
+}
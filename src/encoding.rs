@@ -0,0 +1,160 @@
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes `data` using the RFC 4648 base32 alphabet.
+pub fn base32(data: &[u8], padding: bool) -> String {
+  let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+  for chunk in data.chunks(5) {
+    let mut block = [0u8; 8];
+    block[3..3 + chunk.len()].copy_from_slice(chunk);
+    let bits = u64::from_be_bytes(block);
+
+    let chars = (chunk.len() * 8).div_ceil(5);
+    for i in 0..chars {
+      let index = (bits >> (35 - i * 5)) & 0x1f;
+      out.push(BASE32_ALPHABET[index as usize] as char);
+    }
+
+    if padding {
+      out.extend(std::iter::repeat_n('=', 8 - chars));
+    }
+  }
+
+  out
+}
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Encodes `data` using the Bitcoin base58 alphabet.
+pub fn base58(data: &[u8]) -> String {
+  let zeros = data.iter().take_while(|b| **b == 0).count();
+
+  // little-endian base58 digits of the non-zero part of the input
+  let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 138 / 100 + 1);
+  for byte in &data[zeros..] {
+    let mut carry = *byte as u32;
+    for digit in digits.iter_mut() {
+      carry += (*digit as u32) << 8;
+      *digit = (carry % 58) as u8;
+      carry /= 58;
+    }
+
+    while carry > 0 {
+      digits.push((carry % 58) as u8);
+      carry /= 58;
+    }
+  }
+
+  std::iter::repeat_n(b'1', zeros)
+    .chain(digits.iter().rev().map(|d| BASE58_ALPHABET[*d as usize]))
+    .map(char::from)
+    .collect()
+}
+
+const BECH32_ALPHABET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Encodes `data` as a lowercase BIP 173 bech32 string with the human-readable part `hrp`.
+pub fn bech32(hrp: &str, data: &[u8]) -> String {
+  // regroup the data into 5 bit words, padding the last one with zeros
+  let mut words = Vec::with_capacity((data.len() * 8).div_ceil(5) + 6);
+  let (mut acc, mut bits) = (0u32, 0);
+  for byte in data {
+    acc = (acc << 8) | *byte as u32;
+    bits += 8;
+    while bits >= 5 {
+      bits -= 5;
+      words.push(((acc >> bits) & 0x1f) as u8);
+    }
+  }
+
+  if bits > 0 {
+    words.push(((acc << (5 - bits)) & 0x1f) as u8);
+  }
+
+  let hrp = hrp.to_ascii_lowercase();
+  let expanded_hrp = hrp
+    .bytes()
+    .map(|b| b >> 5)
+    .chain([0])
+    .chain(hrp.bytes().map(|b| b & 0x1f));
+  let values = expanded_hrp.chain(words.iter().copied()).chain([0; 6]);
+  let checksum = bech32_polymod(values) ^ 1;
+  words.extend((0..6).map(|i| ((checksum >> (5 * (5 - i))) & 0x1f) as u8));
+
+  let mut out = hrp;
+  out.push('1');
+  out.extend(words.iter().map(|w| BECH32_ALPHABET[*w as usize] as char));
+  out
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+  const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+  let mut chk = 1u32;
+  for value in values {
+    let top = chk >> 25;
+    chk = ((chk & 0x1ffffff) << 5) ^ value as u32;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+      if (top >> i) & 1 == 1 {
+        chk ^= generator;
+      }
+    }
+  }
+
+  chk
+}
+
+const BASE36_ALPHABET: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Encodes `data` as a big-endian number in lowercase base36, without leading zeros.
+pub fn base36(data: &[u8]) -> String {
+  // little-endian base36 digits of the input
+  let mut digits: Vec<u8> = Vec::with_capacity(data.len() * 155 / 100 + 1);
+  for byte in data {
+    let mut carry = *byte as u32;
+    for digit in digits.iter_mut() {
+      carry += (*digit as u32) << 8;
+      *digit = (carry % 36) as u8;
+      carry /= 36;
+    }
+
+    while carry > 0 {
+      digits.push((carry % 36) as u8);
+      carry /= 36;
+    }
+  }
+
+  if digits.is_empty() {
+    digits.push(0);
+  }
+
+  digits
+    .iter()
+    .rev()
+    .map(|d| char::from(BASE36_ALPHABET[*d as usize]))
+    .collect()
+}
+
+const BASE62_ALPHABET: &[u8; 62] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Encodes `value` in base62, left padded with zeros to `width` characters.
+pub fn base62(mut value: u64, width: usize) -> String {
+  let mut digits = Vec::with_capacity(width);
+  while value > 0 || digits.len() < width {
+    digits.push(BASE62_ALPHABET[(value % 62) as usize]);
+    value /= 62;
+  }
+
+  digits.iter().rev().map(|d| char::from(*d)).collect()
+}
+
+/// Computes the CRC-32 (IEEE 802.3) checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+    }
+  }
+
+  !crc
+}
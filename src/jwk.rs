@@ -0,0 +1,189 @@
+//! JSON Web Keys (RFC 7517) for the public half of generated keypairs.
+
+use openssl::{
+  bn::{BigNum, BigNumContext},
+  error::ErrorStack,
+  hash::{hash, MessageDigest},
+  nid::Nid,
+  pkey::{Id, PKey, Private},
+};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("jwks are not supported for {0} keys")]
+  UnsupportedKey(String),
+
+  #[error(transparent)]
+  Crypto(#[from] ErrorStack),
+}
+
+/// Creates a JWK set containing the public key of `key`.
+///
+/// The `kid` of the key is its RFC 7638 thumbprint, so it only changes along with the key material.
+pub fn jwks(key: &PKey<Private>, alg: Option<&str>, use_: &str) -> Result<String, Error> {
+  let (mut jwk, default_alg) = public_members(key)?;
+
+  // the thumbprint is the hash of the required members without whitespace, in lexicographic order
+  let thumbprint = hash(
+    MessageDigest::sha256(),
+    Value::Object(jwk.clone()).to_string().as_bytes(),
+  )?;
+  jwk.insert("kid".into(), encode(&thumbprint).into());
+  jwk.insert("use".into(), use_.into());
+  jwk.insert("alg".into(), alg.unwrap_or(default_alg).into());
+
+  Ok(json!({ "keys": [jwk] }).to_string())
+}
+
+/// Returns the required public members of the JWK for `key` in lexicographic order, along with the default algorithm
+/// for the key.
+fn public_members(key: &PKey<Private>) -> Result<(Map<String, Value>, &'static str), Error> {
+  let mut jwk = Map::new();
+  let alg = match key.id() {
+    Id::RSA => {
+      let rsa = key.rsa()?;
+      jwk.insert("e".into(), encode(&rsa.e().to_vec()).into());
+      jwk.insert("kty".into(), "RSA".into());
+      jwk.insert("n".into(), encode(&rsa.n().to_vec()).into());
+      "RS256"
+    }
+    Id::EC => {
+      let ec = key.ec_key()?;
+      let (crv, size, alg) = match ec.group().curve_name() {
+        Some(Nid::X9_62_PRIME256V1) => ("P-256", 32, "ES256"),
+        Some(Nid::SECP384R1) => ("P-384", 48, "ES384"),
+        curve => return Err(Error::UnsupportedKey(format!("{curve:?} ec"))),
+      };
+
+      let (mut x, mut y) = (BigNum::new()?, BigNum::new()?);
+      let mut ctx = BigNumContext::new()?;
+      ec.public_key()
+        .affine_coordinates(ec.group(), &mut x, &mut y, &mut ctx)?;
+      jwk.insert("crv".into(), crv.into());
+      jwk.insert("kty".into(), "EC".into());
+      jwk.insert("x".into(), encode(&x.to_vec_padded(size)?).into());
+      jwk.insert("y".into(), encode(&y.to_vec_padded(size)?).into());
+      alg
+    }
+    Id::ED25519 => {
+      jwk.insert("crv".into(), "Ed25519".into());
+      jwk.insert("kty".into(), "OKP".into());
+      jwk.insert("x".into(), encode(&key.raw_public_key()?).into());
+      "EdDSA"
+    }
+    Id::X25519 => {
+      jwk.insert("crv".into(), "X25519".into());
+      jwk.insert("kty".into(), "OKP".into());
+      jwk.insert("x".into(), encode(&key.raw_public_key()?).into());
+      "ECDH-ES"
+    }
+    id => return Err(Error::UnsupportedKey(format!("{id:?}"))),
+  };
+
+  Ok((jwk, alg))
+}
+
+fn encode(data: &[u8]) -> String {
+  base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use openssl::{
+    ec::{EcGroup, EcKey, EcPoint},
+    rsa::Rsa,
+  };
+
+  fn kid(jwks: &str) -> String {
+    let jwks: Value = serde_json::from_str(jwks).unwrap();
+    jwks["keys"][0]["kid"].as_str().unwrap().to_owned()
+  }
+
+  fn decode(data: &str) -> Vec<u8> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).unwrap()
+  }
+
+  #[test]
+  fn rsa_kid_is_the_rfc7638_thumbprint() {
+    // RFC 7638 section 3.1. only the public components are used, so the private ones are placeholders.
+    let n = decode(concat!(
+      "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjh",
+      "Mstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQv",
+      "RL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKn",
+      "qDKgw",
+    ));
+    let one = || BigNum::from_u32(1).unwrap();
+    let rsa = Rsa::from_private_components(
+      BigNum::from_slice(&n).unwrap(),
+      BigNum::from_u32(65537).unwrap(),
+      one(),
+      one(),
+      one(),
+      one(),
+      one(),
+      one(),
+    )
+    .unwrap();
+
+    let jwks = jwks(&PKey::from_rsa(rsa).unwrap(), None, "sig").unwrap();
+    assert_eq!(kid(&jwks), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+  }
+
+  #[test]
+  fn ed25519_kid_is_the_rfc8037_thumbprint() {
+    // RFC 8037 appendix A.3
+    let d = decode("nWGxne_9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A");
+    let key = PKey::private_key_from_raw_bytes(&d, Id::ED25519).unwrap();
+
+    let jwks: Value = serde_json::from_str(&jwks(&key, None, "sig").unwrap()).unwrap();
+    let jwk = &jwks["keys"][0];
+    assert_eq!(jwk["x"], "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo");
+    assert_eq!(jwk["kid"], "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    assert_eq!(jwk["alg"], "EdDSA");
+    assert_eq!(jwk["use"], "sig");
+  }
+
+  #[test]
+  fn p256_coordinates_match_rfc7517() {
+    // RFC 7517 appendix A.2
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let d = BigNum::from_slice(&decode("870MB6gfuTJ4HtUnUvYMyJpr5eUZNP4Bk43bVdj3eAE")).unwrap();
+    let mut public = EcPoint::new(&group).unwrap();
+    let ctx = BigNumContext::new().unwrap();
+    public.mul_generator(&group, &d, &ctx).unwrap();
+    let key = PKey::from_ec_key(EcKey::from_private_components(&group, &d, &public).unwrap()).unwrap();
+
+    let jwks: Value = serde_json::from_str(&jwks(&key, None, "enc").unwrap()).unwrap();
+    let jwk = &jwks["keys"][0];
+    assert_eq!(jwk["crv"], "P-256");
+    assert_eq!(jwk["x"], "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4");
+    assert_eq!(jwk["y"], "4Etl6SRW2YiLUrN5vfvVHuhp7x8PxltmWWlbbM4IFyM");
+    assert_eq!(jwk["kid"], "cn-I_WNMClehiVp51i_0VpOENW1upEerA8sEam5hn-s");
+    assert_eq!(jwk["alg"], "ES256");
+  }
+
+  #[test]
+  fn alg_overrides_the_default() {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let jwks: Value = serde_json::from_str(&jwks(&key, Some("ECDH-ES"), "enc").unwrap()).unwrap();
+    let jwk = &jwks["keys"][0];
+    assert_eq!(jwk["crv"], "P-256");
+    assert_eq!(jwk["alg"], "ECDH-ES");
+    assert_eq!(decode(jwk["x"].as_str().unwrap()).len(), 32);
+  }
+
+  #[test]
+  fn unsupported_keys_are_an_error() {
+    let group = EcGroup::from_curve_name(Nid::SECP521R1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    assert!(matches!(jwks(&key, None, "sig"), Err(Error::UnsupportedKey(_))));
+
+    let key = PKey::generate_x448().unwrap();
+    assert!(matches!(jwks(&key, None, "sig"), Err(Error::UnsupportedKey(_))));
+  }
+}
@@ -1,15 +1,207 @@
+mod argon2;
+mod bcrypt;
+mod cron;
+mod encoding;
+mod jwk;
 mod prelude;
+mod scram;
+mod scrypt;
 mod secret_types;
+mod ssh;
+mod x509;
 
 use prelude::*;
 
+/// Secrets generated by the controller, which are written to a kubernetes secret owned by the autosecret.
 #[derive(CustomResource, Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[kube(group = "webstep.no", version = "v1alpha1", kind = "AutoSecret")]
-#[kube(shortname = "as", namespaced)]
+#[kube(shortname = "as", namespaced, status = "AutoSecretStatus")]
+#[kube(
+  printcolumn = r#"{"name":"Keys", "type":"integer", "description":"Number of secrets", "jsonPath":".status.keyCount"}"#
+)]
+#[kube(
+  printcolumn = r#"{"name":"Ready", "type":"string", "jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#
+)]
+#[kube(
+  printcolumn = r#"{"name":"Secret", "type":"string", "description":"Managed secret", "jsonPath":".status.secretName"}"#
+)]
+#[kube(printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#)]
+#[serde(remote = "Self", rename_all = "camelCase")]
 pub struct AutoSecretSpec {
-  secrets: HashMap<String, AutoSecretType>,
+  /// Name of the managed secret (defaults to the name of the autosecret).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  secret_name: Option<SecretName>,
+  /// Type of the managed secret (defaults to the type required by the secrets, or 'Opaque'). As kubernetes doesn't
+  /// allow changing the type of a secret, changing this recreates the secret.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  secret_type: Option<SecretType>,
+  /// Labels and annotations of the managed secret.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  secret_template: Option<SecretTemplate>,
+  /// Other secrets the keys of the managed secret are copied to.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  targets: Vec<TargetSpec>,
+  /// What happens to the secrets when the autosecret is deleted, or when they are no longer written by it (defaults to
+  /// 'Delete').
+  #[serde(default)]
+  deletion_policy: DeletionPolicy,
+  /// Whether the secrets are owned by the autosecret. Secrets that are written as 'Orphan' are never garbage collected,
+  /// and are kept regardless of the deletion policy (defaults to 'Owned').
+  #[serde(default)]
+  ownership: Ownership,
+  /// Keeps the keys of the managed secret that weren't generated by the controller, such as the ones added by other
+  /// tools, instead of removing every key that isn't in the spec (defaults to false).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  preserve_unmanaged_keys: Option<bool>,
+  /// Creates the secrets as immutable, which lowers the load on the kubelets watching them. As kubernetes doesn't allow
+  /// changing immutable secrets, they are recreated whenever their data changes (defaults to false).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  immutable: Option<bool>,
+  /// Pauses the reconciliation of the autosecret, so that its secrets are neither read nor written until it is
+  /// resumed (defaults to false).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  suspend: Option<bool>,
+  /// Warns with an event once a certificate is within this interval of expiring without being regenerated first, e.g.
+  /// '72h' (defaults to '168h').
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  expiry_warning: Option<RotationInterval>,
+  /// Rotation generation of the secrets, which regenerates all of them at once whenever it's changed, e.g. when they
+  /// may have been compromised (defaults to none).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  rotation_generation: Option<u64>,
+  /// Spreads out the rotation of the secrets by offsetting their rotation intervals by up to this percentage of the
+  /// interval, so that secrets created at the same time aren't all rotated at once. The offsets are derived from the
+  /// uid of the autosecret, so they stay the same (defaults to none).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  rotation_jitter: Option<RotationJitter>,
+  /// Window of time in UTC the secrets are rotated and renewed in once they are due, e.g. 'Mon-Fri 02:00-05:00'.
+  /// Changes to the spec are applied right away (defaults to the window of the controller, if any).
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  maintenance_window: Option<MaintenanceWindow>,
+  /// Deployments and stateful sets in the namespace of the autosecret whose pods are restarted when the values of the
+  /// secrets are rotated, so that they pick up the new values (defaults to none).
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  restart_targets: Vec<RestartTarget>,
+  /// Parameters the secrets inherit when they don't set them.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  defaults: Option<SecretDefaults>,
+  /// Renders keys of the managed secret into another key as a dotenv file.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  env_file: Option<EnvFileSpec>,
+  /// Renders keys of the managed secret into another key as a JSON object.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  json_file: Option<JsonFileSpec>,
+  /// Keeps the last values of keys of the managed secret in a secret named '<secret name>-history'.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  history: Option<HistorySpec>,
+  /// Secrets to generate, by the name of the key they are written to.
+  #[schemars(schema_with = "secrets_schema")]
+  secrets: HashMap<String, SecretSpec>,
 }
 
+/// State of the autosecret, as last observed by the controller.
+#[derive(Clone, Default, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoSecretStatus {
+  /// Generation of the autosecret that was last applied successfully.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  observed_generation: Option<i64>,
+  /// Name of the managed secret, as of the last time it was applied.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  secret_name: Option<String>,
+  /// Number of secrets of the autosecret, as of the last time the managed secret was applied.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  key_count: Option<usize>,
+  /// Latest observations of the state of the autosecret. 'Ready' is true once the secrets are applied, and false with
+  /// the error if the last reconciliation failed.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  conditions: Vec<Condition>,
+  /// Generated keys of the managed secret, as of the last time it was applied.
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  keys: BTreeMap<String, KeyStatus>,
+  /// Secrets that failed to generate in the last reconciliation, which keep their previous values.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  failed_keys: Vec<FailedKey>,
+}
+
+/// State of a generated key of the managed secret.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyStatus {
+  /// Time the value of the key was last generated, if it's known.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  last_generated_time: Option<Time>,
+  /// Hash of the parameters the value was generated from.
+  hash: String,
+  /// Generator of the value.
+  #[serde(rename = "type")]
+  type_: String,
+}
+
+/// Secret that failed to generate.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct FailedKey {
+  /// Name of the secret.
+  name: String,
+  /// Why the secret failed to generate, in CamelCase.
+  reason: String,
+  /// Error the secret failed to generate with.
+  message: String,
+}
+
+impl AutoSecretStatus {
+  /// Condition of the given type, if it's set.
+  fn condition(&self, type_: &str) -> Option<&Condition> {
+    self.conditions.iter().find(|c| c.type_ == type_)
+  }
+
+  /// Replaces the condition of the same type as `condition`. The transition time is kept while the status of the
+  /// condition is unchanged.
+  fn set_condition(&mut self, mut condition: Condition) {
+    match self.conditions.iter_mut().find(|c| c.type_ == condition.type_) {
+      Some(existing) => {
+        if existing.status == condition.status {
+          condition.last_transition_time = existing.last_transition_time.clone();
+        }
+
+        *existing = condition;
+      }
+      None => self.conditions.push(condition),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for AutoSecretSpec {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: serde::Deserializer<'de>,
+  {
+    let mut spec = serde_json::Value::deserialize(deserializer)?;
+    apply_defaults(&mut spec).map_err(serde::de::Error::custom)?;
+    check_secrets(&spec).map_err(serde::de::Error::custom)?;
+    let mut spec = AutoSecretSpec::deserialize(spec).map_err(serde::de::Error::custom)?;
+
+    // the rotation generation is part of the hash of every secret, so that changing it regenerates all of them
+    for secret in spec.secrets.values_mut() {
+      secret.set_rotation_generation(spec.rotation_generation);
+    }
+
+    Ok(spec)
+  }
+}
+
+impl Serialize for AutoSecretSpec {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: serde::Serializer,
+  {
+    AutoSecretSpec::serialize(self, serializer)
+  }
+}
+
+/// Shortest time the controller waits before reconciling an autosecret again to rotate its secrets.
+const MIN_ROTATION_DELAY: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() -> Result<()> {
   setup_logging()?;
@@ -19,6 +211,7 @@ async fn main() -> Result<()> {
     "auto-secret [--crd|-h]" {
       help: bool,
       crd: bool,
+      maintenance_window: Option<MaintenanceWindow>,
     }
 
     /// Print the crd.
@@ -26,6 +219,11 @@ async fn main() -> Result<()> {
       crd = true
     }
 
+    /// Only rotate secrets within <window>, e.g. 'Mon-Fri 02:00-05:00' (UTC), unless the autosecret sets its own.
+    ["--maintenance-window", window] => {
+      maintenance_window = Some(MaintenanceWindow::try_from(window)?);
+    }
+
     /// Print this help.
     ["-h" | "--help"] => {
       println!("{}", HELP);
@@ -48,7 +246,7 @@ async fn main() -> Result<()> {
   info!("starting autosecret-controller");
   info!("press <enter> to force a reconciliation of all objects");
 
-  run_controller(reconcile, error_policy).await?;
+  run_controller(reconcile, error_policy, args.maintenance_window).await?;
 
   info!("controller terminated");
   Ok(())
@@ -59,45 +257,1623 @@ async fn main() -> Result<()> {
   resource.namespace = resource.metadata.namespace.as_deref(),
   resource.name = resource.metadata.name.as_deref(),
 ))]
-async fn reconcile(resource: Arc<AutoSecret>, ctx: Context<Client>) -> Result<Action, ControllerError> {
-  let client = ctx.get_ref().clone();
+async fn reconcile(resource: Arc<AutoSecret>, ctx: Context<Data>) -> Result<Action, ControllerError> {
+  let client = ctx.get_ref().client.clone();
+  let auto_secret_api = Api::<AutoSecret>::namespaced(client.clone(), &resource.namespace()?);
+
+  // the finalizer is added before the autosecret is first applied, and removed once it has been cleaned up
+  finalizer(&auto_secret_api, FINALIZER, resource, |event| async move {
+    match event {
+      FinalizerEvent::Apply(resource) => {
+        // the outcome of every attempt is recorded in the status, even when it failed. the error of the attempt takes
+        // precedence over failing to record it
+        let mut status = resource.status.clone().unwrap_or_default();
+        let result = apply(resource.clone(), client.clone(), ctx.get_ref(), &mut status).await;
+        if result.is_ok() {
+          status.observed_generation = resource.metadata.generation;
+        }
+
+        let ready = status.condition("Ready").cloned();
+        status.set_condition(resource.ready_condition(result.as_ref().err()));
+
+        // failures are published once, rather than every time they are retried
+        if let Err(e) = &result {
+          if status.condition("Ready") != ready.as_ref() {
+            client
+              .publish_event(&resource, EventType::Warning, e.reason(), e.to_string())
+              .await;
+          }
+        }
+
+        if resource.status.as_ref() != Some(&status) {
+          let updated = client.update_status(&resource, &status).await;
+          if let (Ok(_), Err(e)) = (&result, updated) {
+            return Err(e);
+          }
+        }
+
+        result
+      }
+      FinalizerEvent::Cleanup(resource) => cleanup(resource, client).await,
+    }
+  })
+  .await
+  .map_err(ControllerError::from)
+}
+
+/// Cleans up after a deleted autosecret. Secrets are garbage collected along with the autosecret that owns them, so
+/// retained secrets are released before the autosecret is gone.
+async fn cleanup(resource: Arc<AutoSecret>, client: Client) -> Result<Action, ControllerError> {
+  if !resource.retains_secrets() {
+    let note = "secrets are deleted along with the autosecret".to_owned();
+    client
+      .publish_event(&resource, EventType::Normal, "DeletedSecrets", note)
+      .await;
+    return Ok(Action::await_change());
+  }
+
+  let mut retained = Vec::new();
+  for owned in client.get_owned_secrets(&resource).await? {
+    let name = owned.metadata.name.clone().unwrap_or_default();
+    info!("retaining secret {} as the autosecret is deleted", name);
+    owned.release(client.clone(), &resource).await?;
+    retained.push(name);
+  }
+
+  if !retained.is_empty() {
+    let note = format!("retained secrets {}", retained.join(", "));
+    client
+      .publish_event(&resource, EventType::Normal, "RetainedSecrets", note)
+      .await;
+  }
+
+  Ok(Action::await_change())
+}
+
+async fn apply(
+  resource: Arc<AutoSecret>,
+  client: Client,
+  data: &Data,
+  status: &mut AutoSecretStatus,
+) -> Result<Action, ControllerError> {
+  // suspended autosecrets are reconciled once they are changed to be resumed
+  if resource.suspended() {
+    info!("skipping reconciliation as the autosecret is suspended");
+    return Ok(Action::await_change());
+  }
+
+  // an autosecret without secrets would only apply an empty secret
+  let ordered_secrets = resource.ordered_secrets()?;
+  let env_file = resource.env_file()?;
+  let json_file = resource.json_file()?;
+  let history = resource.history()?;
+
+  // the targets and history of this autosecret, and the secrets it previously wrote under names that are no longer in
+  // the spec
+  let secret_name = resource.secret_name()?;
+  let mut target_names = resource.target_names();
+  target_names.extend(history.iter().map(|(_, name, _)| name.clone()));
+  let mut owned_secrets = client.get_owned_secrets(&resource).await?;
+  owned_secrets.retain(|owned| owned.metadata.name.as_ref() != Some(&secret_name));
+  let (existing_targets, owned_secrets): (Vec<_>, Vec<_>) = owned_secrets.into_iter().partition(|owned| {
+    owned
+      .metadata
+      .name
+      .as_ref()
+      .is_some_and(|name| target_names.contains(name))
+  });
 
   // get existing secret (from k8s) or create new empty (in-memory) secret
   // with the correct metadata.
-  let mut secret = client.get_secret_or_default(&resource).await?;
+  let mut secret = client.get_secret_or_default(&resource, &owned_secrets).await?;
+  let live_data = secret.data.clone();
 
   // get secret value pairs from the spec
   let spec_secrets = resource.secrets();
 
-  // remove (in-memory) all secrets from the k8s secret
-  // that does not exist in the spec
-  secret.retain(|name, _| !spec_secrets.contains_key(name));
+  // read the keys of other secrets the spec references
+  let inputs = client.get_inputs(&resource).await?;
+
+  // remove (in-memory) the keys that are no longer produced by the spec
+  remove_stale_keys(&resource, &mut secret, &spec_secrets);
+
+  // kubernetes doesn't allow changing the type of an existing secret,
+  // so it has to be deleted and created anew with the same data.
+  let secret_type = resource.secret_type()?;
+  let recreate = secret.set_type(&secret_type);
+  secret.set_template(&resource.secret_template()?);
+  secret.set_rotation_generation(resource.rotation_generation());
+
+  // secrets can be rotated on demand by annotating the autosecret with the secrets to rotate
+  let requested_rotations = resource.requested_rotations();
+  let (rotate, unknown) = requested_rotations.clone().unwrap_or_default();
+  for name in unknown {
+    warn!(
+      "ignoring request to rotate {}, which isn't a secret of the autosecret",
+      name
+    );
+    let note = format!("Can't rotate {name}, as it isn't a secret of the autosecret");
+    client
+      .publish_event(&resource, EventType::Warning, "UnknownRotation", note)
+      .await;
+  }
+
+  // secrets that are due to be rotated or renewed wait for the maintenance window, if there is one
+  let maintenance_window = resource.maintenance_window(data.maintenance_window.as_ref());
+  let window_opens_in = maintenance_window.map_or(Duration::ZERO, |window| window.opens_in(Utc::now()));
 
   // update or create missing secrets in the k8s secret
-  // that do exist in the spec
-  for (name, secret_spec) in &spec_secrets {
-    match secret.secret_status(name, secret_spec) {
-      SecretStatus::Missing => info!("creating new secret {}", name),
-      SecretStatus::Outdated => info!("updating secret {} due to hash change", name),
-      SecretStatus::Matches => {
-        info!("skipping secret {} due to same hash", name);
-        continue;
-      }
+  // that do exist in the spec, after the secrets they depend on
+  // secrets that fail to generate keep their previous values, and don't stop the other secrets from being applied
+  let GeneratedSecrets {
+    generated,
+    failed_keys,
+    cooldowns,
+  } = generate_secrets(&mut secret, &ordered_secrets, &inputs, &rotate, window_opens_in).await;
+
+  for (name, cooldown) in &cooldowns {
+    let note = format!(
+      "Secret {name} isn't regenerated for another {}s, as it's younger than its minimum age",
+      cooldown.as_secs()
+    );
+    client
+      .publish_event(&resource, EventType::Warning, "RotationCooldown", note)
+      .await;
+  }
+
+  // the secrets that keep failing the same way were already reported
+  for failed_key in failed_keys.iter().filter(|key| !status.failed_keys.contains(key)) {
+    let note = format!("Secret {} failed to generate: {}", failed_key.name, failed_key.message);
+    client
+      .publish_event(&resource, EventType::Warning, "KeyFailed", note)
+      .await;
+  }
+
+  status.set_condition(resource.degraded_condition(&failed_keys));
+  status.failed_keys = failed_keys.clone();
+
+  // previous values are only kept for as long as their ttl
+  for (name, secret_spec) in &ordered_secrets {
+    secret.remove_expired_previous(name, secret_spec);
+  }
+
+  // the expiry of the secrets is recorded, and certificates that are about to expire without being regenerated first
+  // are warned about once for every time they expire
+  let expiry_warning = resource.expiry_warning();
+  for (name, secret_spec) in &ordered_secrets {
+    secret.set_expiry(name, secret_spec);
+    if let Some(expires) = secret.expiry_warning(name, secret_spec, expiry_warning) {
+      warn!("secret {} expires at {} without being rotated first", name, expires);
+      let note = format!("Secret {name} expires at {expires} without being rotated first");
+      client
+        .publish_event(&resource, EventType::Warning, "ExpiringSecret", note)
+        .await;
     }
+  }
 
-    secret.set_secret(name, secret_spec);
+  // the env and json files are rendered from the keys once all of them are generated, so they follow every change to
+  // them
+  if let Some((env_file, keys)) = &env_file {
+    secret.set_env_file(env_file, keys)?;
   }
 
+  if let Some((json_file, keys)) = &json_file {
+    secret.set_json_file(json_file, keys);
+  }
+
+  let next_rotation = next_reconcile_in(
+    &secret,
+    &spec_secrets,
+    window_opens_in,
+    expiry_warning,
+    cooldowns.into_iter().map(|(_, cooldown)| cooldown).collect(),
+  );
+
+  // the workloads are restarted once the rotated values are applied. the restart is recorded on the secret until it's
+  // done, so that a failed restart is retried with the same time instead of being lost
+  let restart_targets = resource.restart_targets();
+  let restarted_at = match restart_targets.is_empty() {
+    true => None,
+    false => secret.pending_restart().or_else(|| {
+      let rotated = !secret.rotated_keys(live_data.as_ref()).is_empty();
+      rotated.then(|| timestamp(Utc::now()))
+    }),
+  };
+  secret.set_pending_restart(restarted_at.as_deref());
+
+  // immutable secrets have to be deleted and created anew
+  // with the new data as well
+  let recreate_immutable = secret.set_immutable(resource.immutable(), live_data.as_ref());
+
+  // copy the keys to the targets
+  let targets = resource.target_secrets(&secret, &existing_targets)?;
+
+  // kubernetes rejects secrets that are too large, which would otherwise be retried forever
+  secret.check_size()?;
+  for (target, _) in &targets {
+    target.check_size()?;
+  }
+
+  // the values are added to the history when they are first seen, and the history is only applied after the secret
+  let history_secret = match &history {
+    Some(history) => {
+      let existing = existing_targets
+        .iter()
+        .find(|existing| existing.metadata.name.as_ref() == Some(&history.1));
+      let (history_secret, pruned) = resource.history_secret(&secret, existing, history)?;
+      if !pruned.is_empty() {
+        warn!(
+          "removing the oldest values {} from the history, as it's too large",
+          pruned.join(", ")
+        );
+        let note = format!(
+          "Removed the oldest values {} from the history, as it's too large",
+          pruned.join(", ")
+        );
+        client
+          .publish_event(&resource, EventType::Warning, "HistoryPruned", note)
+          .await;
+      }
+
+      Some(history_secret)
+    }
+    None => None,
+  };
+
   // apply secret in k8s
-  secret.apply(client).await?;
+  if recreate {
+    warn!("recreating secret as its type changed to {}", secret_type);
+    secret.delete(client.clone()).await?;
+  } else if recreate_immutable {
+    warn!("recreating secret as it is immutable and its data changed");
+    secret.delete(client.clone()).await?;
+  }
 
-  Ok(Action::await_change())
+  // the status of the keys is only updated, and their changes are only published, once they are applied
+  let key_statuses = secret.key_statuses(&ordered_secrets);
+  let data = secret.data.clone().unwrap_or_default();
+  let removed_keys = live_data
+    .iter()
+    .flatten()
+    .filter(|(key, _)| !data.contains_key(*key))
+    .map(|(key, _)| key.clone())
+    .collect::<Vec<_>>();
+  secret.apply_or_recreate(client.clone()).await?;
+  status.keys = key_statuses;
+  status.secret_name = Some(secret_name.clone());
+  status.key_count = Some(ordered_secrets.len());
+
+  for (name, created) in generated {
+    let (reason, note) = match created {
+      true => ("KeyCreated", format!("Created secret {name} in {secret_name}")),
+      false => ("KeyUpdated", format!("Updated secret {name} in {secret_name}")),
+    };
+    client.publish_event(&resource, EventType::Normal, reason, note).await;
+  }
+
+  for key in removed_keys {
+    let note = format!("Removed key {key} from {secret_name}");
+    client
+      .publish_event(&resource, EventType::Normal, "KeyRemoved", note)
+      .await;
+  }
+
+  for (target, recreate) in targets {
+    if recreate {
+      warn!(
+        "recreating target {} as its type or immutable data changed",
+        target.metadata.name.as_deref().unwrap_or_default()
+      );
+      target.delete(client.clone()).await?;
+    }
+
+    target.apply_or_recreate(client.clone()).await?;
+  }
+
+  if let Some(history_secret) = history_secret {
+    history_secret.apply_or_recreate(client.clone()).await?;
+  }
+
+  if let Some(restarted_at) = &restarted_at {
+    for target in restart_targets {
+      match client.restart_workload(&resource, target, restarted_at).await? {
+        true => info!("restarting {} {} as its secrets were rotated", target.kind, target.name),
+        false => {
+          warn!(
+            "can't restart {} {}, as it doesn't exist or the controller isn't allowed to patch it",
+            target.kind, target.name
+          );
+          let note = format!(
+            "Can't restart {} {}, as it doesn't exist or the controller isn't allowed to patch it",
+            target.kind, target.name
+          );
+          client
+            .publish_event(&resource, EventType::Warning, "MissingRestartTarget", note)
+            .await;
+        }
+      }
+    }
+
+    client.clear_pending_restart(&resource).await?;
+  }
+
+  // the request is only cleared once the rotated secrets are applied, so that a failed rotation is retried
+  if requested_rotations.is_some() {
+    let failed_rotations = failed_keys
+      .iter()
+      .filter(|key| rotate.contains(&key.name))
+      .map(|key| key.name.clone())
+      .collect::<Vec<_>>();
+    client.clear_rotation_request(&resource, &failed_rotations).await?;
+  }
+
+  // the secret was renamed or the target was removed, so the secrets are no longer managed
+  for owned in owned_secrets {
+    let owned_name = owned.metadata.name.as_deref().unwrap_or_default();
+    if resource.retains_secrets() {
+      warn!(
+        "retaining secret {} as it is no longer written by the autosecret",
+        owned_name
+      );
+      owned.orphan(client.clone(), &resource).await?;
+    } else {
+      warn!(
+        "deleting secret {} as it is no longer written by the autosecret",
+        owned_name
+      );
+      owned.delete(client.clone()).await?;
+    }
+  }
+
+  // the reconciliation still fails when secrets failed to generate, so that they are retried
+  if !failed_keys.is_empty() {
+    let names = failed_keys.iter().map(|key| key.name.as_str()).collect::<Vec<_>>();
+    return Err(ControllerError::SecretsFailed(names.join(", ")));
+  }
+
+  match next_rotation {
+    Some(delay) => Ok(Action::requeue(delay)),
+    None => Ok(Action::await_change()),
+  }
+}
+
+/// Time until the autosecret is reconciled again: when the next secret is due to be rotated or renewed (once the
+/// maintenance window opens), or warned about, or a previous value is due to be removed, or a postponed regeneration is
+/// allowed. The secrets that are already due wait a little, so that they aren't reconciled in a tight loop.
+fn next_reconcile_in(
+  secret: &Secret,
+  secrets: &HashMap<String, SecretSpec>,
+  window_opens_in: Duration,
+  expiry_warning: Duration,
+  cooldowns: Vec<Duration>,
+) -> Option<Duration> {
+  secrets
+    .iter()
+    .flat_map(|(name, secret_spec)| {
+      let rotation = secret
+        .rotation_in(name, secret_spec)
+        .map(|delay| delay.max(window_opens_in));
+      let renewal = secret.renewal_in(secret_spec).map(|delay| delay.max(window_opens_in));
+      let warning = secret.expiry_warning_in(name, secret_spec, expiry_warning);
+      let previous = secret.previous_expires_in(name, secret_spec);
+      rotation.into_iter().chain(renewal).chain(warning).chain(previous)
+    })
+    .chain(cooldowns)
+    .min()
+    .map(|delay| delay.max(MIN_ROTATION_DELAY))
+}
+
+/// Removes the keys of the `secret` that aren't produced by the `secrets` of the autosecret, or only the ones generated
+/// by the controller when the others are preserved, along with the annotations of the secrets that were removed.
+fn remove_stale_keys(resource: &AutoSecret, secret: &mut Secret, secrets: &HashMap<String, SecretSpec>) {
+  // names of all the keys (including companion keys and previous values) produced by the spec
+  let spec_outputs = secrets
+    .iter()
+    .flat_map(|(name, secret_spec)| {
+      let previous = secret_spec
+        .previous_keys(name)
+        .into_iter()
+        .map(|(_, previous)| previous);
+      secret_spec.outputs(name).into_iter().chain(previous)
+    })
+    .collect::<HashSet<_>>();
+
+  let preserve_unmanaged_keys = resource.preserve_unmanaged_keys();
+  let managed_keys = secret.managed_keys();
+  secret.retain(|name, _| !spec_outputs.contains(name) && (!preserve_unmanaged_keys || managed_keys.contains(name)));
+  secret.retain_entries(|name| !secrets.contains_key(name));
+}
+
+/// Outcome of generating the secrets of an autosecret.
+struct GeneratedSecrets {
+  /// Secrets that were generated, along with whether they were created rather than updated.
+  generated: Vec<(String, bool)>,
+  /// Secrets that failed to generate, which kept their previous values.
+  failed_keys: Vec<FailedKey>,
+  /// Secrets that weren't regenerated as they are younger than their minimum age, along with when they may be.
+  cooldowns: Vec<(String, Duration)>,
+}
+
+/// Generates the `secrets` that are missing, outdated or due into the `secret`, in the order they depend on each other,
+/// and the ones in `rotate` regardless of whether they are due. Rotations and renewals wait for the maintenance window
+/// that opens in `window_opens_in`. Secrets that fail to generate keep their previous values, and don't stop the other
+/// secrets from being generated.
+async fn generate_secrets(
+  secret: &mut Secret,
+  secrets: &[(String, SecretSpec)],
+  inputs: &Inputs,
+  rotate: &BTreeSet<String>,
+  window_opens_in: Duration,
+) -> GeneratedSecrets {
+  let mut cooldowns = Vec::new();
+  let mut failed_keys = Vec::new();
+  let mut generated = Vec::new();
+  for (name, secret_spec) in secrets {
+    secret.set_description(name, secret_spec);
+    let created = if rotate.contains(name) {
+      info!("rotating secret {} as requested", name);
+      secret.force_rotation(name);
+      false
+    } else {
+      // values that are younger than their minimum age aren't regenerated until they reach it
+      let status = secret.secret_status(name, secret_spec, inputs);
+      let cooldown = match status {
+        SecretStatus::Missing | SecretStatus::Matches => None,
+        _ if !secret.regenerates(name, secret_spec) => None,
+        _ => secret.cooldown_in(name, secret_spec),
+      };
+
+      if let Some(cooldown) = cooldown {
+        warn!(
+          "postponing the regeneration of secret {} for {}s, as it is younger than its minimum age",
+          name,
+          cooldown.as_secs()
+        );
+        cooldowns.push((name.clone(), cooldown));
+        continue;
+      }
+
+      match status {
+        SecretStatus::Expired | SecretStatus::Renewing if !window_opens_in.is_zero() => {
+          info!(
+            "deferring the rotation of secret {} until the maintenance window opens",
+            name
+          );
+          continue;
+        }
+        SecretStatus::Missing => info!("creating new secret {}", name),
+        SecretStatus::Outdated => info!("updating secret {} due to hash change", name),
+        SecretStatus::Expired => info!("rotating secret {} as it is older than its rotation interval", name),
+        SecretStatus::Renewing => info!("renewing secret {} as its certificate is about to expire", name),
+        SecretStatus::Matches => {
+          info!("skipping secret {} due to same hash", name);
+          continue;
+        }
+      }
+
+      matches!(status, SecretStatus::Missing)
+    };
+
+    match secret.set_secret(name, secret_spec, inputs).await {
+      Ok(()) => generated.push((name.clone(), created)),
+      Err(e) => {
+        warn!("failed to generate secret {}: {}", name, e);
+        failed_keys.push(FailedKey {
+          name: name.clone(),
+          reason: e.reason().to_owned(),
+          message: e.to_string(),
+        });
+      }
+    }
+  }
+
+  GeneratedSecrets {
+    generated,
+    failed_keys,
+    cooldowns,
+  }
 }
 
 // copy in everything below this line
 
 /// The controller triggers this on reconcile errors
 #[tracing::instrument(skip_all)]
-fn error_policy(_: &ControllerError, _: Context<Client>) -> Action {
-  Action::requeue(Duration::from_secs(15))
+fn error_policy(error: &ControllerError, _: Context<Data>) -> Action {
+  // errors in the spec are only retried once the autosecret changes
+  match error.is_permanent() {
+    true => Action::await_change(),
+    false => Action::requeue(Duration::from_secs(15)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HOUR: Duration = Duration::from_secs(60 * 60);
+
+  fn secrets(secrets: serde_json::Value) -> HashMap<String, SecretSpec> {
+    serde_json::from_value(secrets).unwrap()
+  }
+
+  /// A secret with the values of `generated` generated the given time ago.
+  fn secret(generated: &[(&str, Duration)]) -> Secret {
+    let annotations = generated.iter().map(|(name, ago)| {
+      let generated = Utc::now() - chrono::Duration::from_std(*ago).unwrap();
+      (generated_annotation_name(name), timestamp(generated))
+    });
+
+    let mut secret = Secret::default();
+    secret.metadata.annotations = Some(annotations.collect());
+    secret
+  }
+
+  fn close_to(delay: Option<Duration>, expected: Duration) -> bool {
+    delay.is_some_and(|delay| delay <= expected && delay + Duration::from_secs(60) > expected)
+  }
+
+  #[test]
+  fn requeues_for_the_soonest_rotation() {
+    let secrets = secrets(serde_json::json!({
+      "daily": { "type": "random", "rotateAfter": "24h" },
+      "hourly": { "type": "random", "rotateAfter": "2h" },
+      "never": "random",
+    }));
+    let secret = secret(&[("daily", HOUR), ("hourly", HOUR), ("never", 1000 * HOUR)]);
+
+    let delay = next_reconcile_in(&secret, &secrets, Duration::ZERO, Duration::ZERO, vec![]);
+    assert!(close_to(delay, HOUR), "{delay:?}");
+  }
+
+  #[test]
+  fn overdue_rotations_are_requeued_after_the_minimum_delay() {
+    let secrets = secrets(serde_json::json!({ "overdue": { "type": "random", "rotateAfter": "1h" } }));
+    let delay = next_reconcile_in(
+      &secret(&[("overdue", 3 * HOUR)]),
+      &secrets,
+      Duration::ZERO,
+      Duration::ZERO,
+      vec![],
+    );
+    assert_eq!(delay, Some(MIN_ROTATION_DELAY));
+
+    // keys that were never generated are due right away as well
+    let delay = next_reconcile_in(&secret(&[]), &secrets, Duration::ZERO, Duration::ZERO, vec![]);
+    assert_eq!(delay, Some(MIN_ROTATION_DELAY));
+  }
+
+  #[test]
+  fn secrets_without_rotations_are_not_requeued() {
+    let secrets = secrets(serde_json::json!({ "a": "random", "b": "uuid" }));
+    let delay = next_reconcile_in(
+      &secret(&[("a", HOUR)]),
+      &secrets,
+      Duration::ZERO,
+      Duration::ZERO,
+      vec![],
+    );
+    assert_eq!(delay, None);
+  }
+
+  #[test]
+  fn rotations_wait_for_the_maintenance_window() {
+    let secrets = secrets(serde_json::json!({ "key": { "type": "random", "rotateAfter": "1h" } }));
+    let secret = secret(&[("key", 2 * HOUR)]);
+    let delay = next_reconcile_in(&secret, &secrets, 5 * HOUR, Duration::ZERO, vec![]);
+    assert_eq!(delay, Some(5 * HOUR));
+
+    // a rotation that is due after the window opened isn't moved
+    let secret = self::secret(&[("key", Duration::ZERO)]);
+    let delay = next_reconcile_in(&secret, &secrets, HOUR / 2, Duration::ZERO, vec![]);
+    assert!(close_to(delay, HOUR), "{delay:?}");
+  }
+
+  #[test]
+  fn cooldowns_are_requeued() {
+    let secrets = secrets(serde_json::json!({ "key": { "type": "random", "rotateAfter": "24h" } }));
+    let secret = secret(&[("key", Duration::ZERO)]);
+    let delay = next_reconcile_in(&secret, &secrets, Duration::ZERO, Duration::ZERO, vec![HOUR, 2 * HOUR]);
+    assert_eq!(delay, Some(HOUR));
+  }
+
+  fn auto_secret(secrets: serde_json::Value) -> AutoSecret {
+    serde_json::from_value(serde_json::json!({
+      "apiVersion": "webstep.no/v1alpha1",
+      "kind": "AutoSecret",
+      "metadata": { "name": "app", "namespace": "default", "uid": "uid" },
+      "spec": { "secrets": secrets },
+    }))
+    .unwrap()
+  }
+
+  /// Generates the secrets of `resource` into `secret` the way a reconciliation does.
+  async fn reconcile_secret(resource: &AutoSecret, secret: &mut Secret) -> GeneratedSecrets {
+    remove_stale_keys(resource, secret, &resource.secrets());
+    let secrets = resource.ordered_secrets().unwrap();
+    generate_secrets(secret, &secrets, &Inputs::default(), &BTreeSet::new(), Duration::ZERO).await
+  }
+
+  fn names(generated: &GeneratedSecrets) -> (Vec<&str>, Vec<&str>) {
+    let mut names = generated
+      .generated
+      .iter()
+      .map(|(name, _)| name.as_str())
+      .collect::<Vec<_>>();
+    names.sort();
+    let failed = generated.failed_keys.iter().map(|key| key.name.as_str()).collect();
+    (names, failed)
+  }
+
+  #[tokio::test]
+  async fn failing_secret_doesnt_stop_the_others_until_it_recovers() {
+    let mut secret = Secret::default();
+    let resource = auto_secret(serde_json::json!({ "a": "password", "b": "password", "c": "uuid", "d": "random" }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["a", "b", "c", "d"], vec![]));
+    assert!(generated.generated.iter().all(|(_, created)| *created));
+    let before = secret.data.clone().unwrap();
+
+    // b can't be generated anymore while a is changed and d is removed
+    let resource = auto_secret(serde_json::json!({
+      "a": { "type": "password", "length": 20 },
+      "b": { "type": "password", "length": 3 },
+      "c": "uuid",
+    }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["a"], vec!["b"]));
+    assert_eq!(generated.failed_keys[0].reason, "GenerateFailed");
+
+    let data = secret.data.clone().unwrap();
+    assert_ne!(data["a"], before["a"]);
+    assert_eq!(data["b"], before["b"]);
+    assert_eq!(data["c"], before["c"]);
+    assert!(!data.contains_key("d"));
+    assert!(!secret
+      .key_statuses(&resource.ordered_secrets().unwrap())
+      .contains_key("d"));
+
+    let degraded = resource.degraded_condition(&generated.failed_keys);
+    assert_eq!(
+      (degraded.status.as_str(), degraded.message.as_str()),
+      ("True", "Secrets b failed to generate")
+    );
+
+    // once b can be generated again, it's the only secret that is
+    let resource = auto_secret(serde_json::json!({
+      "a": { "type": "password", "length": 20 },
+      "b": { "type": "password", "length": 28 },
+      "c": "uuid",
+    }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["b"], vec![]));
+    assert_ne!(secret.data.as_ref().unwrap()["b"], before["b"]);
+    assert_eq!(resource.degraded_condition(&generated.failed_keys).status, "False");
+
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec![], vec![]));
+  }
+
+  #[tokio::test]
+  async fn young_outdated_secrets_wait_for_their_minimum_age() {
+    let mut secret = Secret::default();
+    let resource = auto_secret(serde_json::json!({ "key": { "type": "password", "minAge": "1h" } }));
+    reconcile_secret(&resource, &mut secret).await;
+    let before = secret.data.clone().unwrap();
+
+    let resource = auto_secret(serde_json::json!({ "key": { "type": "password", "length": 28, "minAge": "1h" } }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert!(generated.generated.is_empty());
+    assert_eq!(generated.cooldowns.len(), 1);
+    let (name, cooldown) = &generated.cooldowns[0];
+    assert!(name == "key" && close_to(Some(*cooldown), HOUR), "{cooldown:?}");
+    assert_eq!(secret.data.clone().unwrap(), before);
+  }
+
+  #[tokio::test]
+  async fn forced_secrets_are_regenerated_before_their_minimum_age() {
+    let mut secret = Secret::default();
+    let resource = auto_secret(serde_json::json!({ "key": { "type": "password", "minAge": "1h", "force": true } }));
+    reconcile_secret(&resource, &mut secret).await;
+    let before = secret.data.clone().unwrap();
+
+    let resource = auto_secret(serde_json::json!({
+      "key": { "type": "password", "length": 28, "minAge": "1h", "force": true },
+    }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(generated.generated, [("key".to_owned(), false)]);
+    assert!(generated.cooldowns.is_empty());
+    assert_ne!(secret.data.clone().unwrap(), before);
+  }
+
+  fn data_keys(secret: &Secret) -> Vec<&str> {
+    secret.data.iter().flatten().map(|(key, _)| key.as_str()).collect()
+  }
+
+  fn annotated_keys(secret: &Secret) -> Vec<&str> {
+    let annotations = secret.metadata.annotations.iter().flatten();
+    let mut keys = annotations
+      .filter_map(|(annotation, _)| annotation.strip_prefix("autosecrets.webstep.no/"))
+      .collect::<Vec<_>>();
+    keys.sort();
+    keys
+  }
+
+  #[tokio::test]
+  async fn removed_secrets_take_both_their_keys_along() {
+    let kinds = [
+      ("ecdsaKeyPair", "signing.pub"),
+      ("naclBoxKeyPair", "signing.pub"),
+      ("ageKey", "signing.pub"),
+      ("oauthClient", "signing.id"),
+    ];
+
+    for (kind, second_key) in kinds {
+      let mut secret = Secret::default();
+      let resource = auto_secret(serde_json::json!({ "signing": kind, "other": "uuid" }));
+      reconcile_secret(&resource, &mut secret).await;
+      assert_eq!(data_keys(&secret), ["other", "signing", second_key], "{kind}");
+      let annotated = annotated_keys(&secret);
+      assert!(annotated.iter().any(|key| key.contains("signing")), "{annotated:?}");
+
+      let resource = auto_secret(serde_json::json!({ "other": "uuid" }));
+      reconcile_secret(&resource, &mut secret).await;
+      assert_eq!(data_keys(&secret), ["other"], "{kind}");
+      let annotated = annotated_keys(&secret);
+      assert!(!annotated.iter().any(|key| key.contains("signing")), "{annotated:?}");
+    }
+  }
+
+  #[tokio::test]
+  async fn connection_changes_keep_the_postgres_password() {
+    let mut secret = Secret::default();
+    let postgres = |host: &str| {
+      serde_json::json!({
+        "db": { "type": "postgres", "host": host, "database": "app", "username": "app" },
+      })
+    };
+    reconcile_secret(&auto_secret(postgres("old.internal")), &mut secret).await;
+    let before = secret.data.clone().unwrap();
+
+    let generated = reconcile_secret(&auto_secret(postgres("new.internal")), &mut secret).await;
+    assert_eq!(names(&generated), (vec!["db"], vec![]));
+    let data = secret.data.clone().unwrap();
+    assert_eq!(data["db"], before["db"]);
+    let url = String::from_utf8(data["db.url"].0.clone()).unwrap();
+    let (password, host) = url.strip_prefix("postgres://app:").unwrap().split_once('@').unwrap();
+    let password = percent_encoding::percent_decode_str(password).collect::<Vec<_>>();
+    assert_eq!((password, host), (data["db"].0.clone(), "new.internal:5432/app"));
+  }
+
+  #[tokio::test]
+  async fn ssh_auth_secrets_are_created_and_recreated_with_their_type() {
+    let resource = auto_secret(serde_json::json!({ "deploy": { "type": "sshKey", "sshAuth": true } }));
+    assert_eq!(resource.secret_type().unwrap(), "kubernetes.io/ssh-auth");
+
+    // a new secret is created with the type, and later reconciles don't recreate it
+    let mut secret = Secret::default();
+    assert!(!secret.set_type(&resource.secret_type().unwrap()));
+    reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(data_keys(&secret), ["deploy.pub", "ssh-privatekey"]);
+    assert!(!secret.set_type(&resource.secret_type().unwrap()));
+
+    // an existing secret of another type has to be recreated
+    let mut secret = Secret {
+      type_: Some("Opaque".into()),
+      ..Secret::default()
+    };
+    assert!(secret.set_type(&resource.secret_type().unwrap()));
+    assert_eq!(secret.type_.as_deref(), Some("kubernetes.io/ssh-auth"));
+
+    let mut resource = resource;
+    resource.spec.secret_type = Some(serde_json::from_value(serde_json::json!("Opaque")).unwrap());
+    let error = resource.secret_type().unwrap_err();
+    assert!(
+      matches!(&error, ControllerError::SecretTypeMismatch(name, ..) if name == "deploy"),
+      "{error}"
+    );
+  }
+
+  #[tokio::test]
+  async fn changing_the_scrypt_cost_regenerates_the_value_and_its_hash() {
+    let mut secret = Secret::default();
+    let scrypt = |n: u64| serde_json::json!({ "key": { "type": "password", "scrypt": { "n": n, "r": 1 } } });
+    reconcile_secret(&auto_secret(scrypt(16)), &mut secret).await;
+    let before = secret.data.clone().unwrap();
+    assert_eq!(data_keys(&secret), ["key", "key.scrypt"]);
+
+    let generated = reconcile_secret(&auto_secret(scrypt(32)), &mut secret).await;
+    assert_eq!(names(&generated), (vec!["key"], vec![]));
+    let data = secret.data.clone().unwrap();
+    assert_ne!(data["key"], before["key"]);
+    assert_ne!(data["key.scrypt"], before["key.scrypt"]);
+    assert!(data["key.scrypt"].0.starts_with(b"$scrypt$ln=5,r=1,p=1$"));
+  }
+
+  #[tokio::test]
+  async fn cookie_secrets_rotate_together() {
+    let mut secret = Secret::default();
+    reconcile_secret(
+      &auto_secret(serde_json::json!({ "cookie": "cookieSecrets" })),
+      &mut secret,
+    )
+    .await;
+    let before = secret.data.clone().unwrap();
+    assert_eq!(
+      annotated_keys(&secret).iter().filter(|key| **key == "cookie").count(),
+      1
+    );
+
+    let resource = auto_secret(serde_json::json!({ "cookie": { "type": "cookieSecrets", "revision": 1 } }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["cookie"], vec![]));
+    let data = secret.data.clone().unwrap();
+    assert_ne!(data["cookie"], before["cookie"]);
+    assert_ne!(data["cookie.encryption"], before["cookie.encryption"]);
+  }
+
+  /// The `ca.crt` and `ca.key` of a new CA in the secret `issuer`, as the inputs of the autosecrets it issues for.
+  async fn issuer_inputs(issuer: &str) -> Inputs {
+    let mut ca = Secret::default();
+    let spec = serde_json::from_value(serde_json::json!("caCertificate")).unwrap();
+    ca.set_secret("ca", &spec, &Inputs::default()).await.unwrap();
+    let data = ca.data.unwrap().into_iter();
+    data
+      .map(|(key, value)| {
+        (
+          KeyRef {
+            secret: issuer.to_owned(),
+            key,
+          },
+          value.0,
+        )
+      })
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn renamed_certificate_keys_replace_the_old_keys() {
+    let inputs = issuer_inputs("ca").await;
+    let mut secret = Secret::default();
+    let resource = auto_secret(serde_json::json!({ "server": { "type": "signedCertificate", "issuer": "ca" } }));
+    remove_stale_keys(&resource, &mut secret, &resource.secrets());
+    let secrets = resource.ordered_secrets().unwrap();
+    generate_secrets(&mut secret, &secrets, &inputs, &BTreeSet::new(), Duration::ZERO).await;
+    assert_eq!(data_keys(&secret), ["ca.crt", "tls.crt", "tls.key"]);
+
+    let resource = auto_secret(serde_json::json!({
+      "server": { "type": "signedCertificate", "issuer": "ca", "certificateKeyName": "server.pem",
+        "certificatePrivateKeyName": "server-key.pem" },
+    }));
+    remove_stale_keys(&resource, &mut secret, &resource.secrets());
+    let secrets = resource.ordered_secrets().unwrap();
+    let generated = generate_secrets(&mut secret, &secrets, &inputs, &BTreeSet::new(), Duration::ZERO).await;
+    assert_eq!(names(&generated), (vec!["server"], vec![]));
+    assert_eq!(data_keys(&secret), ["ca.crt", "server-key.pem", "server.pem"]);
+    assert!(annotated_keys(&secret).iter().all(|key| key.starts_with("server")));
+  }
+
+  fn live_secret(data: &[(&str, &str)]) -> Secret {
+    let data = data
+      .iter()
+      .map(|(key, value)| (key.to_string(), ByteString(value.as_bytes().to_vec())));
+    Secret {
+      data: Some(data.collect()),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn imported_keys_are_kept_until_they_are_rotated() {
+    let mut secret = live_secret(&[("api", "legacy")]);
+    let resource = auto_secret(serde_json::json!({ "api": { "type": "password", "importIfPresent": true } }));
+    reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(secret.data.as_ref().unwrap()["api"].0, b"legacy");
+    assert_eq!(annotated_keys(&secret), ["api"]);
+
+    // the imported value is tracked like a generated one
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec![], vec![]));
+    assert_eq!(secret.data.as_ref().unwrap()["api"].0, b"legacy");
+
+    let resource = auto_secret(serde_json::json!({
+      "api": { "type": "password", "importIfPresent": true, "revision": 1 },
+    }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["api"], vec![]));
+    assert_ne!(secret.data.as_ref().unwrap()["api"].0, b"legacy");
+  }
+
+  #[tokio::test]
+  async fn existing_keys_are_overwritten_without_importing() {
+    let mut secret = live_secret(&[("api", "legacy")]);
+    let resource = auto_secret(serde_json::json!({ "api": "password" }));
+    let generated = reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(names(&generated), (vec!["api"], vec![]));
+    assert_ne!(secret.data.as_ref().unwrap()["api"].0, b"legacy");
+  }
+
+  #[tokio::test]
+  async fn unmanaged_keys_are_preserved_when_asked_to() {
+    for preserve in [false, true] {
+      let mut resource = auto_secret(serde_json::json!({ "a": "uuid", "b": "uuid" }));
+      resource.spec.preserve_unmanaged_keys = Some(preserve);
+      let mut secret = live_secret(&[("foreign", "added by another tool")]);
+      reconcile_secret(&resource, &mut secret).await;
+      let foreign = preserve.then_some("foreign");
+      assert_eq!(
+        data_keys(&secret),
+        [Some("a"), Some("b"), foreign]
+          .into_iter()
+          .flatten()
+          .collect::<Vec<_>>()
+      );
+
+      // only the managed key that left the spec is removed
+      resource.spec.secrets.remove("b");
+      reconcile_secret(&resource, &mut secret).await;
+      assert_eq!(
+        data_keys(&secret),
+        [Some("a"), foreign].into_iter().flatten().collect::<Vec<_>>()
+      );
+      assert_eq!(annotated_keys(&secret), ["a"]);
+    }
+  }
+
+  #[tokio::test]
+  async fn immutable_secrets_are_recreated_when_their_data_changes() {
+    let mut resource = auto_secret(serde_json::json!({ "key": "password" }));
+    resource.spec.immutable = Some(true);
+    let mut secret = Secret::default();
+    reconcile_secret(&resource, &mut secret).await;
+    assert!(!secret.set_immutable(resource.immutable(), None));
+    assert_eq!(secret.immutable, Some(true));
+
+    // reading the secret back doesn't change it
+    let live_data = secret.data.clone();
+    reconcile_secret(&resource, &mut secret).await;
+    assert!(!secret.set_immutable(resource.immutable(), live_data.as_ref()));
+
+    resource.spec.secrets =
+      serde_json::from_value(serde_json::json!({ "key": { "type": "password", "revision": 1 } })).unwrap();
+    reconcile_secret(&resource, &mut secret).await;
+    assert!(secret.set_immutable(resource.immutable(), live_data.as_ref()));
+    assert_eq!(secret.immutable, Some(true));
+
+    // making the secret mutable again needs a recreate as well
+    let live_data = secret.data.clone();
+    resource.spec.immutable = Some(false);
+    assert!(secret.set_immutable(resource.immutable(), live_data.as_ref()));
+    assert_eq!(secret.immutable, None);
+    assert!(!secret.set_immutable(resource.immutable(), live_data.as_ref()));
+  }
+
+  #[test]
+  fn autosecrets_without_secrets_are_rejected() {
+    let crd = serde_json::to_value(AutoSecret::crd()).unwrap();
+    let spec = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"];
+    assert_eq!(spec["properties"]["secrets"]["minProperties"], 1);
+    assert!(serde_yaml::to_string(&AutoSecret::crd())
+      .unwrap()
+      .contains("minProperties: 1"));
+
+    // autosecrets created before the schema required a secret fail without applying an empty secret
+    let resource = auto_secret(serde_json::json!({}));
+    let error = resource.ordered_secrets().unwrap_err();
+    assert!(matches!(error, ControllerError::NoSecrets) && error.is_permanent());
+    let ready = resource.ready_condition(Some(&error));
+    assert_eq!(
+      (ready.status.as_str(), ready.reason.as_str(), ready.message.as_str()),
+      ("False", "InvalidSpec", "The autosecret doesn't define any secrets")
+    );
+  }
+
+  /// Client of an api server that can't be reached, so that any request made with it fails.
+  fn unreachable_client() -> Client {
+    let config = kube::Config::new("http://127.0.0.1:1".parse().unwrap());
+    Client::try_from(config).unwrap()
+  }
+
+  #[tokio::test]
+  async fn suspended_autosecrets_are_left_alone() {
+    let mut resource = auto_secret(serde_json::json!({ "key": "password" }));
+    let data = Data {
+      client: unreachable_client(),
+      maintenance_window: None,
+    };
+
+    // a changed spec isn't applied either, as neither the autosecret nor its secret is read or written
+    resource.spec.suspend = Some(true);
+    resource.metadata.generation = Some(2);
+    let mut status = AutoSecretStatus::default();
+    let action = apply(Arc::new(resource.clone()), data.client.clone(), &data, &mut status)
+      .await
+      .unwrap();
+    assert_eq!(format!("{action:?}"), format!("{:?}", Action::await_change()));
+    assert_eq!(status, AutoSecretStatus::default());
+
+    let ready = resource.ready_condition(None);
+    assert_eq!((ready.status.as_str(), ready.reason.as_str()), ("False", "Suspended"));
+
+    // once it's resumed, it's reconciled like any other autosecret
+    resource.spec.suspend = None;
+    let result = apply(Arc::new(resource), data.client.clone(), &data, &mut status).await;
+    assert!(result.is_err());
+  }
+
+  #[tokio::test]
+  async fn oversized_secrets_fail_without_being_retried() {
+    let large = "x".repeat(400 * 1024);
+    let resource = auto_secret(serde_json::json!({
+      "small": "uuid",
+      "first": { "type": "literal", "value": large },
+      "second": { "type": "literal", "value": large },
+    }));
+    let mut secret = Secret::default();
+    reconcile_secret(&resource, &mut secret).await;
+    assert!(secret.check_size().is_ok());
+
+    let resource = auto_secret(serde_json::json!({
+      "small": "uuid",
+      "first": { "type": "literal", "value": large },
+      "second": { "type": "literal", "value": large },
+      "third": { "type": "literal", "value": format!("{large}xx") },
+    }));
+    reconcile_secret(&resource, &mut secret).await;
+    let error = secret.check_size().unwrap_err();
+    let ControllerError::SecretTooLarge { size, limit, keys } = &error else {
+      panic!("{error}");
+    };
+    assert!(*size > 3 * large.len() && *limit == 1024 * 1024, "{error}");
+    assert_eq!(
+      keys,
+      "third (409607 bytes), second (409606 bytes), first (409605 bytes)"
+    );
+
+    let context = Context::new(Data {
+      client: unreachable_client(),
+      maintenance_window: None,
+    });
+    let action = error_policy(&error, context);
+    assert_eq!(format!("{action:?}"), format!("{:?}", Action::await_change()));
+    assert_eq!(error.reason(), "InvalidSpec");
+  }
+
+  #[tokio::test]
+  async fn binary_literals_are_stored_as_they_decode() {
+    let resource = auto_secret(serde_json::json!({ "keytab": { "type": "literal", "literalBase64": "AAEC/wB0YWIA" } }));
+    let mut secret = Secret::default();
+    reconcile_secret(&resource, &mut secret).await;
+    assert_eq!(
+      secret.data.unwrap()["keytab"],
+      ByteString(b"\0\x01\x02\xff\0tab\0".to_vec())
+    );
+
+    let invalid = serde_json::from_value::<AutoSecret>(serde_json::json!({
+      "apiVersion": "webstep.no/v1alpha1",
+      "kind": "AutoSecret",
+      "metadata": { "name": "app", "namespace": "default" },
+      "spec": { "secrets": { "keytab": { "type": "literal", "literalBase64": "AAEC/w!" } } },
+    }));
+    let error = invalid.unwrap_err().to_string();
+    assert!(
+      error.starts_with("invalid secret keytab: literalBase64 is not valid base64"),
+      "{error}"
+    );
+  }
+
+  /// Generates the secrets of `resource` into `secret` and renders its JSON file, returning the parsed file.
+  async fn reconcile_json_file(resource: &AutoSecret, secret: &mut Secret) -> serde_json::Value {
+    reconcile_secret(resource, secret).await;
+    let (json_file, keys) = resource.json_file().unwrap().unwrap();
+    secret.set_json_file(&json_file, &keys);
+    serde_json::from_slice(&secret.data.as_ref().unwrap()[&json_file.key].0).unwrap()
+  }
+
+  #[tokio::test]
+  async fn json_file_holds_the_keys_of_the_secret() {
+    let secrets = |revision: u64| {
+      serde_json::json!({
+        "password": { "type": "password", "revision": revision },
+        "id": "uuid",
+        "blob": { "type": "literal", "literalBase64": "3q2+7w==" },
+      })
+    };
+
+    let mut resource = auto_secret(secrets(0));
+    resource.spec.json_file = Some(serde_json::from_value(serde_json::json!({})).unwrap());
+    let mut secret = Secret::default();
+    let file = reconcile_json_file(&resource, &mut secret).await;
+    let text = |key: &str| serde_json::Value::from(std::str::from_utf8(&secret.data.as_ref().unwrap()[key].0).unwrap());
+    let expected = serde_json::json!({ "password": text("password"), "id": text("id"), "blob_base64": "3q2+7w==" });
+    assert_eq!(file, expected);
+
+    // a rotated value is in the file as soon as it's in the secret
+    resource.spec.secrets = serde_json::from_value(secrets(1)).unwrap();
+    let file = reconcile_json_file(&resource, &mut secret).await;
+    let text = |key: &str| serde_json::Value::from(std::str::from_utf8(&secret.data.as_ref().unwrap()[key].0).unwrap());
+    assert_ne!(file["password"], expected["password"]);
+    assert_eq!(file["password"], text("password"));
+
+    let json_file = serde_json::json!({ "key": "config.json", "include": ["blob", "id"], "binary": "exclude" });
+    resource.spec.json_file = Some(serde_json::from_value(json_file).unwrap());
+    let file = reconcile_json_file(&resource, &mut secret).await;
+    assert_eq!(file, serde_json::json!({ "id": expected["id"] }));
+    assert!(!data_keys(&secret).contains(&"secrets.json"));
+  }
+
+  #[test]
+  fn certificates_are_requeued_for_their_renewal() {
+    let mut secret = Secret::default();
+    let secrets = secrets(serde_json::json!({ "ca": { "type": "caCertificate", "validityDays": 3 } }));
+    let spec = &secrets["ca"];
+    let values = spec
+      .generate("ca", &Inputs::default(), &Values::default(), None)
+      .unwrap();
+    secret.data = Some(
+      values
+        .into_iter()
+        .map(|(key, value)| (key, ByteString(value)))
+        .collect(),
+    );
+
+    // a third of the validity is left when the certificate is renewed
+    let delay = next_reconcile_in(&secret, &secrets, Duration::ZERO, Duration::ZERO, vec![]);
+    assert!(close_to(delay, 48 * HOUR), "{delay:?}");
+  }
+
+  #[tokio::test]
+  async fn certificates_are_renewed_before_they_expire() {
+    for reuse_key in [false, true] {
+      let resource = auto_secret(serde_json::json!({
+        "ca": { "type": "caCertificate", "validityDays": 1, "renewBefore": "23h59m58s", "reuseKey": reuse_key },
+      }));
+      let mut secret = Secret::default();
+      reconcile_secret(&resource, &mut secret).await;
+      let before = secret.data.clone().unwrap();
+      let generated = reconcile_secret(&resource, &mut secret).await;
+      assert_eq!(names(&generated), (vec![], vec![]));
+
+      // the spec is unchanged, but the certificate is due to be renewed
+      tokio::time::sleep(Duration::from_millis(2100)).await;
+      let generated = reconcile_secret(&resource, &mut secret).await;
+      assert_eq!(names(&generated), (vec!["ca"], vec![]));
+      let data = secret.data.clone().unwrap();
+      let not_after = |data: &BTreeMap<String, ByteString>| x509::not_after(&data["ca.crt"].0).unwrap();
+      assert!(not_after(&data) > not_after(&before));
+      assert_eq!(data["ca.key"] == before["ca.key"], reuse_key);
+    }
+  }
+
+  /// Request received by a fake api server.
+  #[derive(Clone, Debug)]
+  struct ApiRequest {
+    method: String,
+    path: String,
+    body: serde_json::Value,
+  }
+
+  /// Client of a fake api server that answers every request with the status and body `respond` returns for it, along
+  /// with the requests the server received.
+  fn fake_api(
+    respond: impl Fn(&ApiRequest) -> (u16, serde_json::Value) + Send + Sync + 'static,
+  ) -> (Client, Arc<std::sync::Mutex<Vec<ApiRequest>>>) {
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let respond = Arc::new(respond);
+    let received = requests.clone();
+    let service = tower::service_fn(move |request: http::Request<hyper::Body>| {
+      let (respond, received) = (respond.clone(), received.clone());
+      async move {
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        let request = ApiRequest {
+          method: parts.method.to_string(),
+          path: parts.uri.path().to_owned(),
+          body: serde_json::from_slice(&body).unwrap_or_default(),
+        };
+
+        let (status, body) = respond(&request);
+        received.lock().unwrap().push(request);
+        let response = http::Response::builder().status(status);
+        Ok::<_, hyper::Error>(response.body(hyper::Body::from(body.to_string())).unwrap())
+      }
+    });
+
+    (Client::new(service, "default"), requests)
+  }
+
+  /// Reasons of the events published through the fake api server.
+  fn event_reasons(requests: &[ApiRequest]) -> Vec<&str> {
+    let events = requests.iter().filter(|request| request.path.ends_with("/events"));
+    events.filter_map(|request| request.body["reason"].as_str()).collect()
+  }
+
+  /// Answers the requests of a cleanup with the `secrets` of the namespace, and the patches of the first secret with
+  /// `patch_status`.
+  fn cleanup_api(
+    secrets: Vec<serde_json::Value>,
+    patch_status: u16,
+  ) -> impl Fn(&ApiRequest) -> (u16, serde_json::Value) {
+    move |request| match (request.method.as_str(), request.path.as_str()) {
+      ("GET", "/api/v1/namespaces/default/secrets") => {
+        let list = serde_json::json!({ "apiVersion": "v1", "kind": "SecretList", "metadata": {}, "items": secrets });
+        (200, list)
+      }
+      ("PATCH", _) if patch_status != 200 => (
+        patch_status,
+        serde_json::json!({ "kind": "Status", "code": patch_status, "message": "failed" }),
+      ),
+      ("PATCH", _) => (200, secrets[0].clone()),
+      _ => (200, request.body.clone()),
+    }
+  }
+
+  fn owned_secret(resource: &AutoSecret) -> serde_json::Value {
+    serde_json::json!({
+      "apiVersion": "v1",
+      "kind": "Secret",
+      "metadata": {
+        "name": "app",
+        "namespace": "default",
+        "ownerReferences": resource.owner_references(),
+        "annotations": { "autosecrets.webstep.no/key": "hash", "team": "payments" },
+      },
+      "data": { "key": "dmFsdWU=" },
+    })
+  }
+
+  #[tokio::test]
+  async fn deleted_autosecrets_retain_or_delete_their_secrets() {
+    // deleted secrets are garbage collected by kubernetes, so they're left alone
+    let resource = auto_secret(serde_json::json!({ "key": "uuid" }));
+    let (client, requests) = fake_api(cleanup_api(vec![owned_secret(&resource)], 200));
+    cleanup(Arc::new(resource), client).await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(event_reasons(&requests), ["DeletedSecrets"]);
+    assert!(requests.iter().all(|request| request.method == "POST"), "{requests:?}");
+
+    let mut resource = auto_secret(serde_json::json!({ "key": "uuid" }));
+    resource.spec.deletion_policy = DeletionPolicy::Retain;
+    let (client, requests) = fake_api(cleanup_api(vec![owned_secret(&resource)], 200));
+    cleanup(Arc::new(resource), client).await.unwrap();
+    let requests = requests.lock().unwrap().clone();
+    let patches = requests
+      .iter()
+      .filter(|request| request.method == "PATCH")
+      .collect::<Vec<_>>();
+    assert_eq!(patches.len(), 1, "{requests:?}");
+    assert_eq!(patches[0].path, "/api/v1/namespaces/default/secrets/app");
+    assert_eq!(
+      patches[0].body["metadata"],
+      serde_json::json!({ "ownerReferences": [], "annotations": { "autosecrets.webstep.no/key": null } })
+    );
+    assert_eq!(event_reasons(&requests), ["RetainedSecrets"]);
+  }
+
+  #[tokio::test]
+  async fn cleanups_are_retried_until_they_succeed() {
+    let mut resource = auto_secret(serde_json::json!({ "key": "uuid" }));
+    resource.spec.deletion_policy = DeletionPolicy::Retain;
+
+    // a secret that is already gone has nothing left to clean up
+    let (client, _) = fake_api(cleanup_api(vec![owned_secret(&resource)], 404));
+    cleanup(Arc::new(resource.clone()), client).await.unwrap();
+
+    let (client, requests) = fake_api(cleanup_api(vec![owned_secret(&resource)], 500));
+    let error = cleanup(Arc::new(resource), client.clone()).await.unwrap_err();
+    assert!(matches!(error, ControllerError::SecretApplyFailed(_)), "{error}");
+    assert!(event_reasons(&requests.lock().unwrap()).is_empty());
+
+    let context = Context::new(Data {
+      client,
+      maintenance_window: None,
+    });
+    let action = error_policy(&error, context);
+    assert_eq!(
+      format!("{action:?}"),
+      format!("{:?}", Action::requeue(Duration::from_secs(15)))
+    );
+  }
+
+  #[test]
+  fn rotations_are_spread_out_by_the_uid() {
+    let delay = |uid: &str, jitter: Option<u8>| {
+      let mut resource = auto_secret(serde_json::json!({ "key": { "type": "random", "rotateAfter": "100h" } }));
+      resource.metadata.uid = Some(uid.to_owned());
+      resource.spec.rotation_jitter = jitter.map(|jitter| jitter.try_into().unwrap());
+      let secret = secret(&[("key", Duration::ZERO)]);
+      let delay = next_reconcile_in(&secret, &resource.secrets(), Duration::ZERO, Duration::ZERO, vec![]);
+      delay.unwrap().as_secs_f64() / HOUR.as_secs_f64()
+    };
+
+    // fifty autosecrets of the same release rotate within 10% of the interval, rather than all at once
+    let uids = (0..50).map(|i| format!("0c6b0f5e-{i:04}")).collect::<Vec<_>>();
+    let hours = uids.iter().map(|uid| delay(uid, Some(10))).collect::<Vec<_>>();
+    assert!(hours.iter().all(|hours| (89.99..=110.0).contains(hours)), "{hours:?}");
+    let (min, max) = hours
+      .iter()
+      .fold((f64::MAX, f64::MIN), |(min, max), h| (min.min(*h), max.max(*h)));
+    assert!(max - min > 15.0, "{hours:?}");
+    let distinct = hours.iter().map(|hours| (hours * 10.0) as u64).collect::<HashSet<_>>();
+    assert!(distinct.len() > 40, "{hours:?}");
+
+    // the same autosecret rotates at the same time after a restart
+    for (uid, hours) in uids.iter().zip(&hours) {
+      assert!((delay(uid, Some(10)) - hours).abs() < 0.01);
+    }
+
+    assert!((delay(&uids[0], None) - 100.0).abs() < 0.01);
+    assert!((delay(&uids[0], Some(0)) - 100.0).abs() < 0.01);
+    assert!(RotationJitter::try_from(51).is_err());
+  }
+
+  /// Objects of a fake cluster: the secrets of the namespace by name, the names of its deployments, and the autosecret
+  /// its patches of autosecrets return.
+  #[derive(Default)]
+  struct Cluster {
+    secrets: BTreeMap<String, serde_json::Value>,
+    deployments: Vec<String>,
+    auto_secret: serde_json::Value,
+  }
+
+  fn not_found() -> (u16, serde_json::Value) {
+    let status = serde_json::json!({ "kind": "Status", "status": "Failure", "reason": "NotFound", "code": 404,
+      "message": "not found" });
+    (404, status)
+  }
+
+  /// Client of a fake api server that keeps the secrets and deployments of the `cluster` in the namespace 'default',
+  /// and echoes the events it's sent back.
+  fn cluster_api(cluster: Arc<std::sync::Mutex<Cluster>>) -> (Client, Arc<std::sync::Mutex<Vec<ApiRequest>>>) {
+    fake_api(move |request| {
+      let mut cluster = cluster.lock().unwrap();
+      let path = request.path.strip_prefix("/api/v1/namespaces/default/secrets");
+      match (request.method.as_str(), path.map(|path| path.trim_start_matches('/'))) {
+        ("GET", Some("")) => {
+          let items = cluster.secrets.values().cloned().collect::<Vec<_>>();
+          (
+            200,
+            serde_json::json!({ "apiVersion": "v1", "kind": "SecretList", "metadata": {}, "items": items }),
+          )
+        }
+        ("GET", Some(name)) => cluster
+          .secrets
+          .get(name)
+          .map_or_else(not_found, |secret| (200, secret.clone())),
+        ("DELETE", Some(name)) => match cluster.secrets.remove(name) {
+          Some(_) => (
+            200,
+            serde_json::json!({ "kind": "Status", "status": "Success", "code": 200 }),
+          ),
+          None => not_found(),
+        },
+
+        // server-side applies send the whole secret, and the merge patches of the controller only change annotations
+        ("PATCH", Some(name)) if request.body.get("apiVersion").is_some() => {
+          cluster.secrets.insert(name.to_owned(), request.body.clone());
+          (200, request.body.clone())
+        }
+        ("PATCH", Some(name)) => {
+          let Some(secret) = cluster.secrets.get_mut(name) else {
+            return not_found();
+          };
+          let patch = request.body["metadata"]["annotations"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+          let annotations = secret["metadata"]["annotations"].as_object_mut().unwrap();
+          for (key, value) in patch {
+            match value {
+              serde_json::Value::Null => drop(annotations.remove(&key)),
+              value => drop(annotations.insert(key, value)),
+            }
+          }
+          (200, secret.clone())
+        }
+        _ => match request
+          .path
+          .strip_prefix("/apis/apps/v1/namespaces/default/deployments/")
+        {
+          Some(name) if cluster.deployments.iter().any(|deployment| deployment == name) => {
+            let deployment = serde_json::json!({ "apiVersion": "apps/v1", "kind": "Deployment",
+              "metadata": { "name": name } });
+            (200, deployment)
+          }
+          Some(_) => not_found(),
+          None if request.path.starts_with("/apis/webstep.no/") => (200, cluster.auto_secret.clone()),
+          None => (200, request.body.clone()),
+        },
+      }
+    })
+  }
+
+  /// Applies `resource` to the fake `cluster` the way a reconciliation does, returning the requests it made.
+  async fn apply_to(cluster: &Arc<std::sync::Mutex<Cluster>>, resource: &AutoSecret) -> Vec<ApiRequest> {
+    let (client, requests) = cluster_api(cluster.clone());
+    let data = Data {
+      client: client.clone(),
+      maintenance_window: None,
+    };
+    let mut status = resource.status.clone().unwrap_or_default();
+    apply(Arc::new(resource.clone()), client, &data, &mut status)
+      .await
+      .unwrap();
+    let requests = requests.lock().unwrap().clone();
+    requests
+  }
+
+  #[tokio::test]
+  async fn rotations_restart_the_target_workloads() {
+    let cluster = Arc::new(std::sync::Mutex::new(Cluster {
+      deployments: vec!["web".into()],
+      ..Default::default()
+    }));
+    let secrets = |revision: u64| serde_json::json!({ "key": { "type": "password", "revision": revision } });
+    let mut resource = auto_secret(secrets(0));
+    resource.spec.restart_targets = serde_json::from_value(serde_json::json!([
+      { "kind": "Deployment", "name": "web" },
+      { "kind": "Deployment", "name": "gone" },
+    ]))
+    .unwrap();
+    let restarts = |requests: &[ApiRequest]| {
+      let restarts = requests.iter().filter(|request| request.path.contains("/deployments/"));
+      restarts.cloned().collect::<Vec<_>>()
+    };
+
+    // neither creating the secret nor reconciling it unchanged restarts anything
+    assert!(restarts(&apply_to(&cluster, &resource).await).is_empty());
+    assert!(restarts(&apply_to(&cluster, &resource).await).is_empty());
+
+    resource.spec.secrets = serde_json::from_value(secrets(1)).unwrap();
+    let requests = apply_to(&cluster, &resource).await;
+    let restarted = restarts(&requests);
+    let paths = restarted
+      .iter()
+      .map(|request| request.path.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      paths,
+      [
+        "/apis/apps/v1/namespaces/default/deployments/web",
+        "/apis/apps/v1/namespaces/default/deployments/gone",
+      ]
+    );
+    let annotations = &restarted[0].body["spec"]["template"]["metadata"]["annotations"];
+    let restarted_at = annotations["autosecrets.webstep.no/restartedAt"].as_str().unwrap();
+    assert!(DateTime::parse_from_rfc3339(restarted_at).is_ok(), "{restarted_at}");
+    assert_eq!(restarted[1].body, restarted[0].body);
+    assert_eq!(event_reasons(&requests), ["KeyUpdated", "MissingRestartTarget"]);
+
+    // the restart is done, so it isn't pending anymore
+    let annotations = cluster.lock().unwrap().secrets["app"]["metadata"]["annotations"].clone();
+    assert!(annotations.get("restart.autosecrets.webstep.no/pending").is_none());
+    assert!(restarts(&apply_to(&cluster, &resource).await).is_empty());
+  }
+
+  /// Reconciles `resource` with its finalizer added against the fake `cluster`, returning the outcome and the requests
+  /// it made.
+  async fn reconcile_in(
+    cluster: &Arc<std::sync::Mutex<Cluster>>,
+    resource: &AutoSecret,
+  ) -> (Result<Action, ControllerError>, Vec<ApiRequest>) {
+    let mut resource = resource.clone();
+    resource.metadata.finalizers = Some(vec![FINALIZER.to_owned()]);
+    cluster.lock().unwrap().auto_secret = serde_json::json!(resource);
+    let (client, requests) = cluster_api(cluster.clone());
+    let context = Context::new(Data {
+      client,
+      maintenance_window: None,
+    });
+    let result = reconcile(Arc::new(resource), context).await;
+    let requests = requests.lock().unwrap().clone();
+    (result, requests)
+  }
+
+  /// Bodies of the status patches among the `requests`.
+  fn status_patches(requests: &[ApiRequest]) -> Vec<&serde_json::Value> {
+    let patches = requests.iter().filter(|request| request.path.ends_with("/status"));
+    patches.map(|request| &request.body["status"]).collect()
+  }
+
+  #[tokio::test]
+  async fn observed_generation_follows_the_applied_spec() {
+    let cluster = Arc::new(std::sync::Mutex::new(Cluster::default()));
+    let mut resource = auto_secret(serde_json::json!({ "key": "password" }));
+    resource.metadata.generation = Some(1);
+    let (result, requests) = reconcile_in(&cluster, &resource).await;
+    result.unwrap();
+    let status = status_patches(&requests)[0].clone();
+    assert_eq!(status["observedGeneration"], 1);
+
+    // a spec edit that fails to apply leaves the status at the generation that was applied
+    resource.status = Some(serde_json::from_value(status).unwrap());
+    resource.spec.secrets =
+      serde_json::from_value(serde_json::json!({ "key": { "type": "password", "length": 3 } })).unwrap();
+    resource.metadata.generation = Some(2);
+    let (result, requests) = reconcile_in(&cluster, &resource).await;
+    assert!(result.is_err());
+    let patch = status_patches(&requests)[0].clone();
+    assert!(patch.get("observedGeneration").is_none(), "{patch}");
+    let mut status = serde_json::json!(resource.status);
+    for (key, value) in patch.as_object().unwrap() {
+      status[key] = value.clone();
+    }
+    assert_eq!(status["observedGeneration"], 1);
+
+    // once the edit is fixed, the status catches up
+    resource.status = Some(serde_json::from_value(status).unwrap());
+    resource.spec.secrets =
+      serde_json::from_value(serde_json::json!({ "key": { "type": "password", "length": 30 } })).unwrap();
+    resource.metadata.generation = Some(3);
+    let (result, requests) = reconcile_in(&cluster, &resource).await;
+    result.unwrap();
+    assert_eq!(status_patches(&requests)[0]["observedGeneration"], 3);
+  }
+
+  #[tokio::test]
+  async fn printer_columns_resolve_against_the_status() {
+    let crd = AutoSecret::crd();
+    let columns = crd.spec.versions[0].additional_printer_columns.clone().unwrap();
+    let paths = columns
+      .iter()
+      .map(|column| (column.name.as_str(), column.json_path.as_str()));
+    assert_eq!(
+      paths.collect::<Vec<_>>(),
+      [
+        ("Keys", ".status.keyCount"),
+        ("Ready", ".status.conditions[?(@.type==\"Ready\")].status"),
+        ("Secret", ".status.secretName"),
+        ("Age", ".metadata.creationTimestamp"),
+      ]
+    );
+
+    let cluster = Arc::new(std::sync::Mutex::new(Cluster::default()));
+    let resource = auto_secret(serde_json::json!({ "a": "password", "b": "password" }));
+    let (result, requests) = reconcile_in(&cluster, &resource).await;
+    result.unwrap();
+    let status = status_patches(&requests)[0].clone();
+    assert_eq!(status["keyCount"], 2);
+    assert_eq!(status["secretName"], "app");
+    let conditions = status["conditions"].as_array().unwrap();
+    let ready = conditions
+      .iter()
+      .find(|condition| condition["type"] == "Ready")
+      .unwrap();
+    assert_eq!(ready["status"], "True");
+  }
+
+  #[tokio::test]
+  async fn each_key_change_publishes_its_event() {
+    let cluster = Arc::new(std::sync::Mutex::new(Cluster::default()));
+    let mut resource = auto_secret(serde_json::json!({ "a": "password", "b": "password" }));
+    assert_eq!(
+      event_reasons(&apply_to(&cluster, &resource).await),
+      ["KeyCreated", "KeyCreated"]
+    );
+
+    // a no-op reconciliation says nothing
+    assert!(event_reasons(&apply_to(&cluster, &resource).await).is_empty());
+
+    resource.spec.secrets =
+      serde_json::from_value(serde_json::json!({ "a": { "type": "password", "revision": 1 }, "b": "password" }))
+        .unwrap();
+    assert_eq!(event_reasons(&apply_to(&cluster, &resource).await), ["KeyUpdated"]);
+
+    resource.spec.secrets =
+      serde_json::from_value(serde_json::json!({ "a": { "type": "password", "revision": 1 } })).unwrap();
+    assert_eq!(event_reasons(&apply_to(&cluster, &resource).await), ["KeyRemoved"]);
+
+    resource.spec.secrets = serde_json::from_value(serde_json::json!({
+      "a": { "type": "password", "revision": 1 },
+      "c": { "type": "password", "length": 3 },
+    }))
+    .unwrap();
+    let (client, requests) = cluster_api(cluster.clone());
+    let data = Data {
+      client: client.clone(),
+      maintenance_window: None,
+    };
+    let mut status = AutoSecretStatus::default();
+    assert!(apply(Arc::new(resource), client, &data, &mut status).await.is_err());
+    assert_eq!(event_reasons(&requests.lock().unwrap()), ["KeyFailed"]);
+  }
 }
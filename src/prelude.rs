@@ -1,15 +1,29 @@
 use futures::{Stream, TryFuture};
 use kube::runtime::{controller, reflector::ObjectRef, watcher};
 
-pub use super::secret_types::AutoSecretType;
+pub use super::secret_types::{
+  DeletionPolicy, EnvFileSpec, GenerateError, HistorySpec, Inputs, JsonFileSpec, KeyRef, MaintenanceWindow, Ownership,
+  RestartTarget, RotationInterval, RotationJitter, SecretDefaults, SecretName, SecretSpec, SecretTemplate, SecretType,
+  TargetSpec, Values, WorkloadKind,
+};
 pub use color_eyre::Result;
 pub use futures::StreamExt;
-pub use k8s_openapi::{api::core::v1::Secret, ByteString};
+pub use k8s_openapi::{
+  api::{
+    apps::v1::{Deployment, StatefulSet},
+    core::v1::Secret,
+  },
+  apimachinery::pkg::apis::meta::v1::{Condition, OwnerReference, Time},
+  chrono::{self, DateTime, SecondsFormat, Utc},
+  ByteString,
+};
 pub use kube::{
-  api::{ListParams, Patch, PatchParams},
+  api::{DeleteParams, ListParams, Patch, PatchParams},
   core::ObjectMeta,
   runtime::{
     controller::{Action, Context},
+    events::{Event, EventType, Recorder, Reporter},
+    finalizer::{finalizer, Event as FinalizerEvent},
     Controller,
   },
   Api, Client, CustomResource, CustomResourceExt, Resource,
@@ -17,7 +31,7 @@ pub use kube::{
 pub use schemars::JsonSchema;
 pub use serde::{Deserialize, Serialize};
 pub use std::{
-  collections::{BTreeMap, HashMap},
+  collections::{BTreeMap, BTreeSet, HashMap, HashSet},
   hash::{Hash, Hasher},
   io::BufRead,
   sync::Arc,
@@ -28,10 +42,21 @@ pub use tracing::{info, warn};
 pub use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
 pub use tracing_tree::HierarchicalLayer;
 
-pub async fn run_controller<F, E, ReconcilerFut>(reconcile: F, error_policy: E) -> Result<()>
+/// State shared by the reconciliations of the autosecrets.
+pub struct Data {
+  pub client: Client,
+  /// Window the secrets are rotated in, unless the autosecret sets its own.
+  pub maintenance_window: Option<MaintenanceWindow>,
+}
+
+pub async fn run_controller<F, E, ReconcilerFut>(
+  reconcile: F,
+  error_policy: E,
+  maintenance_window: Option<MaintenanceWindow>,
+) -> Result<()>
 where
-  F: FnMut(Arc<super::AutoSecret>, Context<Client>) -> ReconcilerFut,
-  E: FnMut(&ReconcilerFut::Error, Context<Client>) -> Action,
+  F: FnMut(Arc<super::AutoSecret>, Context<Data>) -> ReconcilerFut,
+  E: FnMut(&ReconcilerFut::Error, Context<Data>) -> Action,
   ReconcilerFut: TryFuture<Ok = Action, Error = ControllerError> + Send + 'static,
 {
   let client = Client::try_default().await?;
@@ -39,10 +64,36 @@ where
   let autosecrets = Api::<super::AutoSecret>::all(client.clone());
   let secrets = Api::<Secret>::all(client.clone());
 
-  Controller::new(autosecrets, ListParams::default())
-    .owns(secrets, ListParams::default())
+  let controller = Controller::new(autosecrets, ListParams::default());
+  let store = controller.store();
+
+  controller
+    .owns(secrets.clone(), ListParams::default())
+    .watches(secrets, ListParams::default(), move |secret| {
+      // reconcile the autosecrets that read from the changed secret, or wrote it without being its owner
+      let owner = owner_name(&secret).cloned();
+      let namespace = secret.metadata.namespace;
+      let name = secret.metadata.name;
+      store
+        .state()
+        .into_iter()
+        .filter(|auto_secret| auto_secret.metadata.namespace == namespace)
+        .filter(|auto_secret| {
+          let references = auto_secret.references();
+          references.iter().any(|r| Some(&r.secret) == name.as_ref()) || auto_secret.metadata.name == owner
+        })
+        .map(|auto_secret| ObjectRef::from_obj(&*auto_secret))
+        .collect::<Vec<_>>()
+    })
     .handle_signals()
-    .run(reconcile, error_policy, Context::new(client))
+    .run(
+      reconcile,
+      error_policy,
+      Context::new(Data {
+        client,
+        maintenance_window,
+      }),
+    )
     .for_each(log_reconciler_result)
     .await;
 
@@ -97,14 +148,62 @@ impl ControllerExt for Controller<super::AutoSecret> {
 
 #[async_trait::async_trait]
 pub trait ClientExt {
-  async fn get_secret_or_default(&self, auto_secret: &super::AutoSecret) -> Result<Secret, ControllerError>;
+  async fn get_owned_secrets(&self, auto_secret: &super::AutoSecret) -> Result<Vec<Secret>, ControllerError>;
+  async fn get_secret_or_default(
+    &self,
+    auto_secret: &super::AutoSecret,
+    owned_secrets: &[Secret],
+  ) -> Result<Secret, ControllerError>;
+  async fn get_inputs(&self, auto_secret: &super::AutoSecret) -> Result<Inputs, ControllerError>;
+  async fn clear_rotation_request(
+    &self,
+    auto_secret: &super::AutoSecret,
+    failed: &[String],
+  ) -> Result<(), ControllerError>;
+  async fn restart_workload(
+    &self,
+    auto_secret: &super::AutoSecret,
+    target: &RestartTarget,
+    restarted_at: &str,
+  ) -> Result<bool, ControllerError>;
+  async fn clear_pending_restart(&self, auto_secret: &super::AutoSecret) -> Result<(), ControllerError>;
+  async fn publish_event(&self, auto_secret: &super::AutoSecret, type_: EventType, reason: &str, note: String);
+  async fn update_status(
+    &self,
+    auto_secret: &super::AutoSecret,
+    status: &super::AutoSecretStatus,
+  ) -> Result<(), ControllerError>;
 }
 
 #[async_trait::async_trait]
 impl ClientExt for Client {
-  async fn get_secret_or_default(&self, auto_secret: &super::AutoSecret) -> Result<Secret, ControllerError> {
-    let oref = auto_secret.controller_owner_ref(&()).unwrap();
+  /// Secrets controlled by `auto_secret`, which includes the secrets it wrote before its secret name was changed. The
+  /// secrets it wrote without an owner are matched by their owner annotation instead.
+  async fn get_owned_secrets(&self, auto_secret: &super::AutoSecret) -> Result<Vec<Secret>, ControllerError> {
+    let namespace = auto_secret.namespace()?;
     let name = auto_secret.name()?;
+    let uid = auto_secret.meta().uid.as_ref();
+    let secret_api = Api::<Secret>::namespaced(self.clone(), &namespace);
+
+    let secrets = list_secrets(&secret_api).await?;
+    Ok(
+      secrets
+        .into_iter()
+        .filter(|secret| {
+          let mut owners = secret.metadata.owner_references.iter().flatten();
+          owners.any(|owner| owner.controller == Some(true) && Some(&owner.uid) == uid)
+            || owner_name(secret) == Some(&name)
+        })
+        .collect(),
+    )
+  }
+
+  async fn get_secret_or_default(
+    &self,
+    auto_secret: &super::AutoSecret,
+    owned_secrets: &[Secret],
+  ) -> Result<Secret, ControllerError> {
+    let name = auto_secret.secret_name()?;
     let namespace = auto_secret.namespace()?;
 
     let secret_api = Api::<Secret>::namespaced(self.clone(), &namespace);
@@ -112,7 +211,7 @@ impl ClientExt for Client {
       metadata: ObjectMeta {
         name: Some(name.clone()),
         namespace: Some(namespace.clone()),
-        owner_references: Some(vec![oref]),
+        owner_references: auto_secret.owner_references(),
         ..ObjectMeta::default()
       },
       ..Default::default()
@@ -121,22 +220,206 @@ impl ClientExt for Client {
     let existing_secret = get_secret(&secret_api, &name).await?;
     if let Some(existing_secret) = existing_secret {
       secret.metadata.annotations = existing_secret.metadata.annotations.map(|mut annotations| {
-        annotations.retain(|k, _| k.starts_with(ANNOTATION_PREFIX));
+        annotations.retain(|k, _| is_reserved_key(k));
         annotations
       });
 
       secret.data = existing_secret.data;
+      secret.type_ = existing_secret.type_;
+      secret.immutable = existing_secret.immutable;
+    } else if let Some(previous_secret) = owned_secrets
+      .iter()
+      .find(|owned| !is_target(owned) && !is_history(owned))
+    {
+      // the secret was renamed, so its values are carried over to the new secret instead of being regenerated.
+      // the type isn't, as the new secret is created with the type of the spec regardless.
+      secret.metadata.annotations = previous_secret.metadata.annotations.clone().map(|mut annotations| {
+        annotations.retain(|k, _| is_reserved_key(k));
+        annotations
+      });
+
+      secret.data = previous_secret.data.clone();
     }
 
+    let annotations = secret.metadata.annotations.get_or_insert_with(Default::default);
+    annotations.insert(OWNER_ANNOTATION.to_owned(), auto_secret.name()?);
     Ok(secret)
   }
+
+  /// Values of the keys of other secrets referenced by `auto_secret`. References that don't exist are left out, so that
+  /// only the secrets referencing them fail to generate.
+  async fn get_inputs(&self, auto_secret: &super::AutoSecret) -> Result<Inputs, ControllerError> {
+    let namespace = auto_secret.namespace()?;
+    let secret_api = Api::<Secret>::namespaced(self.clone(), &namespace);
+
+    let mut secrets = HashMap::new();
+    let mut inputs = Inputs::new();
+    for reference in auto_secret.references() {
+      if !secrets.contains_key(&reference.secret) {
+        let secret = get_secret(&secret_api, &reference.secret).await?;
+        secrets.insert(reference.secret.clone(), secret);
+      }
+
+      let value = secrets[&reference.secret]
+        .as_ref()
+        .and_then(|s| s.data.as_ref())
+        .and_then(|d| d.get(&reference.key));
+
+      if let Some(ByteString(value)) = value {
+        inputs.insert(reference.clone(), value.clone());
+      }
+    }
+
+    Ok(inputs)
+  }
+
+  /// Removes the rotation request from `auto_secret`, leaving only the `failed` rotations to be retried. The autosecret
+  /// is left as it is when every requested rotation failed, as changing it would reconcile it again right away.
+  async fn clear_rotation_request(
+    &self,
+    auto_secret: &super::AutoSecret,
+    failed: &[String],
+  ) -> Result<(), ControllerError> {
+    let name = auto_secret.name()?;
+    let namespace = auto_secret.namespace()?;
+    let auto_secret_api = Api::<super::AutoSecret>::namespaced(self.clone(), &namespace);
+
+    let patch = match rotation_request_patch(auto_secret, failed) {
+      Some(patch) => patch,
+      None => return Ok(()),
+    };
+
+    auto_secret_api
+      .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+      .await
+      .map_err(ControllerError::AnnotationUpdateFailed)?;
+
+    Ok(())
+  }
+
+  /// Restarts the pods of the workload `target` by annotating its pod template with the time of the restart. Returns
+  /// false when the workload doesn't exist, or the controller isn't allowed to patch it.
+  async fn restart_workload(
+    &self,
+    auto_secret: &super::AutoSecret,
+    target: &RestartTarget,
+    restarted_at: &str,
+  ) -> Result<bool, ControllerError> {
+    let namespace = auto_secret.namespace()?;
+    let patch = restart_patch(restarted_at);
+    let params = PatchParams::default();
+    let result = match target.kind {
+      WorkloadKind::Deployment => Api::<Deployment>::namespaced(self.clone(), &namespace)
+        .patch(&target.name, &params, &Patch::Merge(&patch))
+        .await
+        .map(|_| ()),
+      WorkloadKind::StatefulSet => Api::<StatefulSet>::namespaced(self.clone(), &namespace)
+        .patch(&target.name, &params, &Patch::Merge(&patch))
+        .await
+        .map(|_| ()),
+    };
+
+    match result {
+      Ok(()) => Ok(true),
+      Err(kube::Error::Api(response)) if matches!(response.code, 403 | 404) => Ok(false),
+      Err(e) => Err(ControllerError::RestartFailed(
+        format!("{} {}", target.kind, target.name),
+        e,
+      )),
+    }
+  }
+
+  /// Removes the pending restart from the managed secret of `auto_secret`, once its workloads are restarted.
+  async fn clear_pending_restart(&self, auto_secret: &super::AutoSecret) -> Result<(), ControllerError> {
+    let name = auto_secret.secret_name()?;
+    let namespace = auto_secret.namespace()?;
+    let secret_api = Api::<Secret>::namespaced(self.clone(), &namespace);
+
+    let patch = serde_json::json!({ "metadata": { "annotations": { PENDING_RESTART_ANNOTATION: null } } });
+    secret_api
+      .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+      .await
+      .map_err(ControllerError::AnnotationUpdateFailed)?;
+
+    Ok(())
+  }
+
+  /// Publishes an event about `auto_secret`. Events are only informational, so failing to publish one is logged rather
+  /// than failing the reconciliation.
+  async fn publish_event(&self, auto_secret: &super::AutoSecret, type_: EventType, reason: &str, note: String) {
+    let reporter = Reporter {
+      controller: REPORTER.to_owned(),
+      instance: None,
+    };
+
+    let recorder = Recorder::new(self.clone(), reporter, auto_secret.object_ref(&()));
+    let event = Event {
+      type_,
+      reason: reason.to_owned(),
+      note: Some(note),
+      action: "Reconcile".to_owned(),
+      secondary: None,
+    };
+
+    if let Err(e) = recorder.publish(event).await {
+      warn!("failed to publish event {}: {}", reason, e);
+    }
+  }
+
+  /// Writes the status of `auto_secret` through the status subresource, which leaves its generation unchanged.
+  async fn update_status(
+    &self,
+    auto_secret: &super::AutoSecret,
+    status: &super::AutoSecretStatus,
+  ) -> Result<(), ControllerError> {
+    let name = auto_secret.name()?;
+    let namespace = auto_secret.namespace()?;
+    let auto_secret_api = Api::<super::AutoSecret>::namespaced(self.clone(), &namespace);
+
+    let patch = serde_json::json!({ "status": status_patch(auto_secret.status.as_ref(), status) });
+    auto_secret_api
+      .patch_status(&name, &PatchParams::default(), &Patch::Merge(&patch))
+      .await
+      .map_err(ControllerError::StatusUpdateFailed)?;
+
+    Ok(())
+  }
 }
 
 #[async_trait::async_trait]
 pub trait AutoSecretExt {
   fn namespace(&self) -> Result<String, ControllerError>;
   fn name(&self) -> Result<String, ControllerError>;
-  fn secrets(&self) -> HashMap<String, super::AutoSecretType>;
+  fn secret_name(&self) -> Result<String, ControllerError>;
+  fn retains_secrets(&self) -> bool;
+  fn owner_references(&self) -> Option<Vec<OwnerReference>>;
+  fn preserve_unmanaged_keys(&self) -> bool;
+  fn immutable(&self) -> bool;
+  fn suspended(&self) -> bool;
+  fn rotation_generation(&self) -> Option<u64>;
+  fn expiry_warning(&self) -> Duration;
+  fn maintenance_window<'a>(&'a self, default: Option<&'a MaintenanceWindow>) -> Option<&'a MaintenanceWindow>;
+  fn secrets(&self) -> HashMap<String, super::SecretSpec>;
+  fn ordered_secrets(&self) -> Result<Vec<(String, super::SecretSpec)>, ControllerError>;
+  fn secret_type(&self) -> Result<String, ControllerError>;
+  fn secret_template(&self) -> Result<SecretTemplate, ControllerError>;
+  fn target_names(&self) -> HashSet<String>;
+  fn requested_rotations(&self) -> Option<(BTreeSet<String>, Vec<String>)>;
+  fn env_file(&self) -> Result<Option<(EnvFileSpec, Vec<String>)>, ControllerError>;
+  fn json_file(&self) -> Result<Option<(JsonFileSpec, Vec<String>)>, ControllerError>;
+  fn history(&self) -> Result<Option<(HistorySpec, String, Vec<String>)>, ControllerError>;
+  fn history_secret(
+    &self,
+    secret: &Secret,
+    existing: Option<&Secret>,
+    history: &(HistorySpec, String, Vec<String>),
+  ) -> Result<(Secret, Vec<String>), ControllerError>;
+  fn target_secrets(&self, secret: &Secret, existing: &[Secret]) -> Result<Vec<(Secret, bool)>, ControllerError>;
+  fn references(&self) -> BTreeSet<KeyRef>;
+  fn restart_targets(&self) -> &[RestartTarget];
+  fn jittered(&self, name: &str, spec: &super::SecretSpec) -> super::SecretSpec;
+  fn ready_condition(&self, error: Option<&ControllerError>) -> Condition;
+  fn degraded_condition(&self, failed_keys: &[super::FailedKey]) -> Condition;
 }
 
 #[async_trait::async_trait]
@@ -157,23 +440,594 @@ impl AutoSecretExt for super::AutoSecret {
       .ok_or(ControllerError::MissingObjectKey(".metadata.name"))
   }
 
-  fn secrets(&self) -> HashMap<String, super::AutoSecretType> {
-    self.spec.secrets.clone()
+  /// Name of the managed secret, which defaults to the name of the autosecret.
+  fn secret_name(&self) -> Result<String, ControllerError> {
+    match &self.spec.secret_name {
+      Some(secret_name) => Ok(secret_name.as_str().to_owned()),
+      None => self.name(),
+    }
+  }
+
+  /// Whether the secrets are kept when the autosecret is deleted, or when they are no longer written by it.
+  fn retains_secrets(&self) -> bool {
+    self.spec.deletion_policy == DeletionPolicy::Retain || self.spec.ownership == Ownership::Orphan
+  }
+
+  /// Owner references of the secrets written by the autosecret. Leaving them out of the applied secret removes the
+  /// owner reference the controller applied before.
+  fn owner_references(&self) -> Option<Vec<OwnerReference>> {
+    match self.spec.ownership {
+      Ownership::Owned => Some(vec![self.controller_owner_ref(&()).unwrap()]),
+      Ownership::Orphan => None,
+    }
+  }
+
+  fn preserve_unmanaged_keys(&self) -> bool {
+    self.spec.preserve_unmanaged_keys.unwrap_or(false)
+  }
+
+  fn immutable(&self) -> bool {
+    self.spec.immutable.unwrap_or(false)
+  }
+
+  fn suspended(&self) -> bool {
+    self.spec.suspend.unwrap_or(false)
+  }
+
+  fn rotation_generation(&self) -> Option<u64> {
+    self.spec.rotation_generation
+  }
+
+  /// Window the secrets are rotated in, which defaults to the one of the controller.
+  fn maintenance_window<'a>(&'a self, default: Option<&'a MaintenanceWindow>) -> Option<&'a MaintenanceWindow> {
+    self.spec.maintenance_window.as_ref().or(default)
+  }
+
+  /// How long before secrets expire they are warned about, which defaults to a week.
+  fn expiry_warning(&self) -> Duration {
+    let expiry_warning = self.spec.expiry_warning.as_ref();
+    expiry_warning.map_or(Duration::from_secs(7 * 24 * 60 * 60), RotationInterval::duration)
+  }
+
+  fn secrets(&self) -> HashMap<String, super::SecretSpec> {
+    let secrets = self.spec.secrets.iter();
+    secrets
+      .map(|(name, spec)| (name.clone(), self.jittered(name, spec)))
+      .collect()
+  }
+
+  fn ordered_secrets(&self) -> Result<Vec<(String, super::SecretSpec)>, ControllerError> {
+    if self.spec.secrets.is_empty() {
+      return Err(ControllerError::NoSecrets);
+    }
+
+    // sorted by name, so that secrets without dependencies between them are generated in a stable order
+    let secrets = self.spec.secrets.iter().collect::<BTreeMap<_, _>>();
+    let mut writers = BTreeMap::<String, Vec<&str>>::new();
+    for (name, spec) in &secrets {
+      let previous = spec.previous_keys(name).into_iter().map(|(_, previous)| previous);
+      for output in spec.outputs(name).into_iter().chain(previous) {
+        if !is_valid_key(&output) {
+          return Err(ControllerError::InvalidKeyName(output));
+        }
+
+        writers.entry(output).or_default().push(name.as_str());
+      }
+    }
+
+    // all of the keys written more than once are reported, including the ones written twice by the same secret
+    let conflicts = writers
+      .iter()
+      .filter(|(_, writers)| writers.len() > 1)
+      .map(|(key, writers)| format!("{key} by {}", writers.join(" and ")))
+      .collect::<Vec<_>>();
+    if !conflicts.is_empty() {
+      return Err(ControllerError::ConflictingOutputs(conflicts.join(", ")));
+    }
+
+    let producers = writers
+      .into_iter()
+      .map(|(key, writers)| (key, writers[0]))
+      .collect::<HashMap<_, _>>();
+
+    let mut ordered = Vec::new();
+    for name in secrets.keys() {
+      visit_dependencies(name, &secrets, &producers, &mut Vec::new(), &mut ordered)?;
+    }
+
+    Ok(
+      ordered
+        .into_iter()
+        .map(|name| (name.to_owned(), self.jittered(name, secrets[&name.to_owned()])))
+        .collect(),
+    )
+  }
+
+  fn references(&self) -> BTreeSet<KeyRef> {
+    self.spec.secrets.values().flat_map(|spec| spec.references()).collect()
+  }
+
+  fn restart_targets(&self) -> &[RestartTarget] {
+    &self.spec.restart_targets
+  }
+
+  /// The secret `name` with the offset of its rotation interval, which is seeded by the uid of the autosecret.
+  fn jittered(&self, name: &str, spec: &super::SecretSpec) -> super::SecretSpec {
+    let mut spec = spec.clone();
+    let uid = self.metadata.uid.as_deref().unwrap_or_default();
+    spec.set_rotation_jitter(self.spec.rotation_jitter.map(|jitter| jitter.offset(uid, name)));
+    spec
+  }
+
+  /// Ready condition of the autosecret after a reconciliation that failed with `error`, if it failed.
+  fn ready_condition(&self, error: Option<&ControllerError>) -> Condition {
+    let (status, reason, message) = match error {
+      Some(error) => (false, error.reason(), error.to_string()),
+      None if self.suspended() => (false, "Suspended", "The autosecret is suspended".to_owned()),
+      None => (true, "Applied", "The secrets are applied".to_owned()),
+    };
+
+    condition("Ready", status, reason, message, self.metadata.generation)
+  }
+
+  /// Degraded condition of the autosecret after a reconciliation in which the `failed_keys` failed to generate.
+  fn degraded_condition(&self, failed_keys: &[super::FailedKey]) -> Condition {
+    let (status, reason, message) = match failed_keys {
+      [] => (false, "Generated", "The secrets are generated".to_owned()),
+      failed_keys => {
+        let names = failed_keys.iter().map(|key| key.name.as_str()).collect::<Vec<_>>();
+        let message = format!("Secrets {} failed to generate", names.join(", "));
+        (true, "GenerateFailed", message)
+      }
+    };
+
+    condition("Degraded", status, reason, message, self.metadata.generation)
+  }
+
+  fn secret_type(&self) -> Result<String, ControllerError> {
+    let secret_type = match &self.spec.secret_type {
+      Some(secret_type) => check_secret_type(&self.spec.secrets, secret_type)?,
+      None => required_secret_type(&self.spec.secrets)?,
+    };
+
+    // kubernetes rejects secrets without the keys their type requires, so it's reported before generating anything
+    let outputs = self
+      .spec
+      .secrets
+      .iter()
+      .flat_map(|(name, spec)| spec.outputs(name))
+      .collect::<HashSet<_>>();
+
+    match SecretType::missing_key(&secret_type, |key| outputs.contains(key)) {
+      Some(key) => Err(ControllerError::MissingSecretTypeKey(secret_type, key)),
+      None => Ok(secret_type),
+    }
+  }
+
+  fn secret_template(&self) -> Result<SecretTemplate, ControllerError> {
+    let template = self.spec.secret_template.clone().unwrap_or_default();
+    let mut keys = template.labels.keys().chain(template.annotations.keys());
+    match keys.find(|key| is_reserved_key(key)) {
+      Some(key) => Err(ControllerError::ReservedTemplateKey(key.clone())),
+      None => Ok(template),
+    }
+  }
+
+  fn target_names(&self) -> HashSet<String> {
+    let targets = self.spec.targets.iter();
+    targets.map(|target| target.name.as_str().to_owned()).collect()
+  }
+
+  /// Secrets that are requested to be rotated by the rotate annotation, which is either 'all' or a comma separated list
+  /// of secrets, along with the requested names that aren't secrets of the autosecret.
+  fn requested_rotations(&self) -> Option<(BTreeSet<String>, Vec<String>)> {
+    let annotations = self.metadata.annotations.as_ref()?;
+    let requested = annotations.get(ROTATE_ANNOTATION)?.trim();
+    if requested == "all" {
+      return Some((self.spec.secrets.keys().cloned().collect(), Vec::new()));
+    }
+
+    let names = requested.split(',').map(str::trim).filter(|name| !name.is_empty());
+    let (names, unknown): (Vec<_>, Vec<_>) = names
+      .map(str::to_owned)
+      .partition(|name| self.spec.secrets.contains_key(name));
+
+    Some((names.into_iter().collect(), unknown))
+  }
+
+  /// The env file of the managed secret if there is one, along with the keys written to it.
+  fn env_file(&self) -> Result<Option<(EnvFileSpec, Vec<String>)>, ControllerError> {
+    let env_file = match &self.spec.env_file {
+      Some(env_file) => env_file.clone(),
+      None => return Ok(None),
+    };
+
+    let keys = bundle_keys(
+      &self.spec.secrets,
+      "envFile",
+      &env_file.key,
+      env_file.include.as_deref(),
+    )?;
+    let mut names = HashMap::new();
+    for key in &keys {
+      if let Some(other) = names.insert(env_file.env_name(key), key) {
+        return Err(ControllerError::ConflictingEnvName(
+          env_file.env_name(key),
+          other.clone(),
+          key.clone(),
+        ));
+      }
+    }
+
+    Ok(Some((env_file, keys)))
+  }
+
+  /// The JSON file of the managed secret if there is one, along with the keys written to it.
+  fn json_file(&self) -> Result<Option<(JsonFileSpec, Vec<String>)>, ControllerError> {
+    let json_file = match &self.spec.json_file {
+      Some(json_file) => json_file.clone(),
+      None => return Ok(None),
+    };
+
+    if let Some(env_file) = self
+      .spec
+      .env_file
+      .as_ref()
+      .filter(|env_file| env_file.key == json_file.key)
+    {
+      let conflict = format!("{} by envFile and jsonFile", env_file.key);
+      return Err(ControllerError::ConflictingOutputs(conflict));
+    }
+
+    let keys = bundle_keys(
+      &self.spec.secrets,
+      "jsonFile",
+      &json_file.key,
+      json_file.include.as_deref(),
+    )?;
+    Ok(Some((json_file, keys)))
+  }
+
+  /// The history of the autosecret, along with the name of its secret and the keys it keeps.
+  fn history(&self) -> Result<Option<(HistorySpec, String, Vec<String>)>, ControllerError> {
+    let history = match &self.spec.history {
+      Some(history) => history.clone(),
+      None => return Ok(None),
+    };
+
+    let name = format!("{}-history", self.secret_name()?);
+    if let Err(e) = SecretName::try_from(name.clone()) {
+      return Err(ControllerError::InvalidHistoryName(name, e));
+    }
+
+    if self.target_names().contains(&name) {
+      return Err(ControllerError::DuplicateTarget(name));
+    }
+
+    let outputs = self.spec.secrets.iter().flat_map(|(name, spec)| spec.outputs(name));
+    let outputs = outputs.collect::<BTreeSet<_>>();
+    let keys = match &history.include {
+      Some(include) => match include.iter().find(|key| !outputs.contains(*key)) {
+        Some(missing) => return Err(ControllerError::MissingBundleKey("history", missing.clone())),
+        None => include.clone(),
+      },
+      None => outputs.into_iter().collect(),
+    };
+
+    // the entries are longer than the keys, so they have to be checked on their own
+    if let Some(key) = keys
+      .iter()
+      .find(|key| !is_valid_key(&HistorySpec::entry(key, Utc::now())))
+    {
+      return Err(ControllerError::InvalidKeyName(HistorySpec::entry(key, Utc::now())));
+    }
+
+    Ok(Some((history, name, keys)))
+  }
+
+  /// The history secret with the values of `secret` that aren't in the `existing` history yet, keeping the last values
+  /// of every key up to the limit. Entries are removed oldest first until the secret fits in the size limit of
+  /// kubernetes, which are returned along with the secret.
+  fn history_secret(
+    &self,
+    secret: &Secret,
+    existing: Option<&Secret>,
+    (history, name, keys): &(HistorySpec, String, Vec<String>),
+  ) -> Result<(Secret, Vec<String>), ControllerError> {
+    let now = Utc::now();
+    let values = secret.data.clone().unwrap_or_default();
+    let entries = existing.and_then(|existing| existing.data.clone()).unwrap_or_default();
+
+    let mut data = BTreeMap::new();
+    for key in keys {
+      // the entries are ordered by time, as the times are formatted to sort the same way
+      let mut kept = entries
+        .iter()
+        .filter(|(entry, _)| HistorySpec::parse_entry(entry).is_some_and(|(entry_key, _)| entry_key == key))
+        .collect::<Vec<_>>();
+
+      let entry = HistorySpec::entry(key, now);
+      match values.get(key) {
+        Some(value) if kept.last().map(|(_, last)| *last) != Some(value) => kept.push((&entry, value)),
+        _ => {}
+      }
+
+      let removed = kept.len().saturating_sub(history.limit.get());
+      data.extend(
+        kept
+          .into_iter()
+          .skip(removed)
+          .map(|(entry, value)| (entry.clone(), value.clone())),
+      );
+    }
+
+    let mut history_secret = Secret {
+      metadata: ObjectMeta {
+        name: Some(name.clone()),
+        namespace: Some(self.namespace()?),
+        owner_references: self.owner_references(),
+        annotations: Some(BTreeMap::from([
+          (HISTORY_ANNOTATION.to_owned(), self.secret_name()?),
+          (OWNER_ANNOTATION.to_owned(), self.name()?),
+        ])),
+        ..ObjectMeta::default()
+      },
+      immutable: existing.and_then(|existing| existing.immutable),
+      data: Some(data),
+      ..Default::default()
+    };
+
+    let mut pruned = Vec::new();
+    while history_secret.check_size().is_err() {
+      let data = history_secret.data.get_or_insert_with(Default::default);
+      let oldest = data
+        .keys()
+        .min_by_key(|entry| HistorySpec::parse_entry(entry).map(|(_, time)| time))
+        .cloned();
+      match oldest {
+        Some(oldest) => {
+          data.remove(&oldest);
+          pruned.push(oldest);
+        }
+        None => break,
+      }
+    }
+
+    Ok((history_secret, pruned))
+  }
+
+  /// Renders the targets from the keys of the managed `secret`, along with whether they have to be recreated as their
+  /// type differs from the `existing` targets.
+  fn target_secrets(&self, secret: &Secret, existing: &[Secret]) -> Result<Vec<(Secret, bool)>, ControllerError> {
+    let secret_name = self.secret_name()?;
+    let namespace = self.namespace()?;
+    let data = secret.data.clone().unwrap_or_default();
+    let annotations = secret.metadata.annotations.clone().unwrap_or_default();
+
+    let mut names = HashSet::from([secret_name.clone()]);
+    let mut targets = Vec::new();
+    for target in &self.spec.targets {
+      let name = target.name.as_str();
+      if !names.insert(name.to_owned()) {
+        return Err(ControllerError::DuplicateTarget(name.to_owned()));
+      }
+
+      let keys = match &target.keys {
+        Some(keys) => keys.clone(),
+        None => data.keys().map(|key| (key.clone(), key.clone())).collect(),
+      };
+
+      let existing = existing
+        .iter()
+        .find(|existing| existing.metadata.name.as_deref() == Some(name));
+      let mut target_secret = Secret {
+        metadata: ObjectMeta {
+          name: Some(name.to_owned()),
+          namespace: Some(namespace.clone()),
+          owner_references: self.owner_references(),
+          ..ObjectMeta::default()
+        },
+        type_: existing.and_then(|existing| existing.type_.clone()),
+        immutable: existing.and_then(|existing| existing.immutable),
+        ..Default::default()
+      };
+
+      let mut target_data = BTreeMap::new();
+      let mut target_annotations = BTreeMap::from([
+        (TARGET_ANNOTATION.to_owned(), secret_name.clone()),
+        (OWNER_ANNOTATION.to_owned(), self.name()?),
+      ]);
+      for (key, source) in keys {
+        if !is_valid_key(&key) {
+          return Err(ControllerError::InvalidKeyName(key));
+        }
+
+        let value = data
+          .get(&source)
+          .ok_or_else(|| ControllerError::MissingTargetKey(name.to_owned(), source.clone()))?;
+
+        // the hashes of the secrets the keys are copied from are kept, so that changes to the target can be traced
+        let producer = self
+          .spec
+          .secrets
+          .iter()
+          .find(|(n, spec)| spec.outputs(n).contains(&source));
+        if let Some((producer, _)) = producer {
+          let annotation_name = annotation_name(producer);
+          if let Some(hash) = annotations.get(&annotation_name) {
+            target_annotations.insert(annotation_name, hash.clone());
+          }
+        }
+
+        target_data.insert(key, value.clone());
+      }
+
+      let target_type = target.secret_type.as_ref().map_or(DEFAULT_SECRET_TYPE, |t| t.as_str());
+      if let Some(key) = SecretType::missing_key(target_type, |key| target_data.contains_key(key)) {
+        return Err(ControllerError::MissingSecretTypeKey(target_type.to_owned(), key));
+      }
+
+      let recreate = target_secret.set_type(target_type);
+      target_secret.metadata.annotations = Some(target_annotations);
+      target_secret.data = Some(target_data);
+      let live_data = existing.and_then(|existing| existing.data.as_ref());
+      let recreate_immutable = target_secret.set_immutable(self.immutable(), live_data);
+      targets.push((target_secret, recreate || recreate_immutable));
+    }
+
+    Ok(targets)
+  }
+}
+
+/// Checks that `secret_type` is the type required by the secrets that require one.
+fn check_secret_type(
+  secrets: &HashMap<String, super::SecretSpec>,
+  secret_type: &SecretType,
+) -> Result<String, ControllerError> {
+  let mut required = secrets
+    .iter()
+    .filter_map(|(name, spec)| spec.secret_type().map(|t| (name, t)))
+    .collect::<Vec<_>>();
+
+  // sort for stable error messages, as the secrets are stored in a hash map
+  required.sort();
+  match required.into_iter().find(|(_, t)| *t != secret_type.as_str()) {
+    Some((name, t)) => Err(ControllerError::SecretTypeMismatch(
+      name.clone(),
+      t,
+      secret_type.as_str().to_owned(),
+    )),
+    None => Ok(secret_type.as_str().to_owned()),
+  }
+}
+
+/// The type required by `secrets`, which has to be the same for all of them.
+fn required_secret_type(secrets: &HashMap<String, super::SecretSpec>) -> Result<String, ControllerError> {
+  let mut types = secrets
+    .iter()
+    .filter_map(|(name, spec)| spec.secret_type().map(|t| (name, t)))
+    .collect::<Vec<_>>();
+
+  // sort for stable error messages, as the secrets are stored in a hash map
+  types.sort();
+  match types.iter().find(|(_, t)| *t != types[0].1) {
+    Some((name, t)) => Err(ControllerError::ConflictingSecretTypes(
+      types[0].0.clone(),
+      types[0].1,
+      (*name).clone(),
+      t,
+    )),
+    None => Ok(types.first().map(|(_, t)| *t).unwrap_or(DEFAULT_SECRET_TYPE).to_owned()),
+  }
+}
+
+/// Keys of the `secrets` that are written to the `bundle` under `key`, which are the `include`d keys or all keys
+/// written by the secrets.
+fn bundle_keys(
+  secrets: &HashMap<String, super::SecretSpec>,
+  bundle: &'static str,
+  key: &str,
+  include: Option<&[String]>,
+) -> Result<Vec<String>, ControllerError> {
+  let outputs = secrets
+    .iter()
+    .flat_map(|(name, spec)| spec.outputs(name).into_iter().map(move |output| (output, name)))
+    .collect::<BTreeMap<_, _>>();
+
+  if !is_valid_key(key) {
+    return Err(ControllerError::InvalidKeyName(key.to_owned()));
+  }
+
+  if let Some(writer) = outputs.get(key) {
+    return Err(ControllerError::ConflictingOutputs(format!(
+      "{key} by {bundle} and {writer}"
+    )));
+  }
+
+  match include {
+    Some(include) => match include.iter().find(|key| !outputs.contains_key(*key)) {
+      Some(missing) => Err(ControllerError::MissingBundleKey(bundle, missing.clone())),
+      None => Ok(include.to_vec()),
+    },
+    None => Ok(outputs.into_keys().collect()),
+  }
+}
+
+/// Adds `name` to `ordered` after all of the secrets it depends on, where `visiting` are the secrets currently being
+/// visited.
+fn visit_dependencies<'a>(
+  name: &'a str,
+  secrets: &BTreeMap<&'a String, &'a super::SecretSpec>,
+  producers: &HashMap<String, &'a str>,
+  visiting: &mut Vec<&'a str>,
+  ordered: &mut Vec<&'a str>,
+) -> Result<(), ControllerError> {
+  if ordered.contains(&name) {
+    return Ok(());
+  }
+
+  if let Some(index) = visiting.iter().position(|n| *n == name) {
+    let mut cycle = visiting[index..].to_vec();
+    cycle.push(name);
+    return Err(ControllerError::CircularDependency(cycle.join(" -> ")));
+  }
+
+  visiting.push(name);
+  for key in secrets[&name.to_owned()].dependencies() {
+    match producers.get(&key) {
+      Some(dependency) => visit_dependencies(dependency, secrets, producers, visiting, ordered)?,
+      None => return Err(ControllerError::MissingDependency(name.to_owned(), key)),
+    }
   }
+
+  visiting.pop();
+  ordered.push(name);
+  Ok(())
 }
 
 pub enum SecretStatus {
   Missing,
   Outdated,
+  Expired,
+  Renewing,
   Matches,
 }
 
 #[async_trait::async_trait]
 pub trait SecretExt {
   fn retain(&mut self, filter: impl FnMut(&str, &ByteString) -> bool) -> bool;
-  fn secret_status(&self, name: &str, spec: &super::AutoSecretType) -> SecretStatus;
-  fn set_secret(&mut self, name: &str, spec: &super::AutoSecretType);
+  fn retain_entries(&mut self, filter: impl FnMut(&str) -> bool) -> bool;
+  fn managed_keys(&self) -> HashSet<String>;
+  fn set_type(&mut self, secret_type: &str) -> bool;
+  fn set_template(&mut self, template: &SecretTemplate);
+  fn set_immutable(&mut self, immutable: bool, live_data: Option<&BTreeMap<String, ByteString>>) -> bool;
+  fn set_description(&mut self, name: &str, spec: &super::SecretSpec);
+  fn set_rotation_generation(&mut self, rotation_generation: Option<u64>);
+  fn rotated_keys(&self, live_data: Option<&BTreeMap<String, ByteString>>) -> Vec<String>;
+  fn key_statuses(&self, secrets: &[(String, super::SecretSpec)]) -> BTreeMap<String, super::KeyStatus>;
+  fn pending_restart(&self) -> Option<String>;
+  fn set_pending_restart(&mut self, restarted_at: Option<&str>);
+  fn force_rotation(&mut self, name: &str);
+  fn set_env_file(&mut self, env_file: &EnvFileSpec, keys: &[String]) -> Result<(), ControllerError>;
+  fn set_json_file(&mut self, json_file: &JsonFileSpec, keys: &[String]);
+  fn check_size(&self) -> Result<(), ControllerError>;
+  fn secret_status(&self, name: &str, spec: &super::SecretSpec, inputs: &Inputs) -> SecretStatus;
+  fn rotation_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration>;
+  fn cooldown_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration>;
+  fn regenerates(&self, name: &str, spec: &super::SecretSpec) -> bool;
+  fn renewal_in(&self, spec: &super::SecretSpec) -> Option<Duration>;
+  fn previous_expires_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration>;
+  fn expiry(&self, name: &str, spec: &super::SecretSpec) -> Option<(DateTime<Utc>, bool)>;
+  fn set_expiry(&mut self, name: &str, spec: &super::SecretSpec);
+  fn expiry_warning(&mut self, name: &str, spec: &super::SecretSpec, threshold: Duration) -> Option<DateTime<Utc>>;
+  fn expiry_warning_in(&self, name: &str, spec: &super::SecretSpec, threshold: Duration) -> Option<Duration>;
+  fn remove_expired_previous(&mut self, name: &str, spec: &super::SecretSpec);
+  async fn set_secret(&mut self, name: &str, spec: &super::SecretSpec, inputs: &Inputs) -> Result<(), ControllerError>;
+  async fn delete(&self, client: Client) -> Result<(), ControllerError>;
+  async fn orphan(&self, client: Client, owner: &super::AutoSecret) -> Result<(), ControllerError>;
+  async fn release(&self, client: Client, owner: &super::AutoSecret) -> Result<(), ControllerError>;
   async fn apply(self, client: Client) -> Result<(), ControllerError>;
+  async fn apply_or_recreate(self, client: Client) -> Result<(), ControllerError>;
 }
 
 #[async_trait::async_trait]
@@ -184,7 +1038,7 @@ impl SecretExt for Secret {
 
     let to_remove = data
       .iter()
-      .filter(|(n, v)| filter(*n, *v))
+      .filter(|(n, v)| filter(n, v))
       .map(|(n, _)| n)
       .cloned()
       .collect::<Vec<_>>();
@@ -192,68 +1046,835 @@ impl SecretExt for Secret {
     let modified = !to_remove.is_empty();
 
     for name in to_remove {
-      remove_secret(annotations, data, &*name);
+      remove_secret(annotations, data, &name);
     }
 
     modified
   }
 
-  fn secret_status(&self, name: &str, spec: &super::AutoSecretType) -> SecretStatus {
-    let annotations = match self.metadata.annotations.as_ref() {
-      None => return SecretStatus::Missing,
-      Some(v) => v,
-    };
+  fn retain_entries(&mut self, mut filter: impl FnMut(&str) -> bool) -> bool {
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    let to_remove = annotations
+      .keys()
+      .filter(|k| k.strip_prefix(ANNOTATION_PREFIX).map(&mut filter).unwrap_or(false))
+      .cloned()
+      .collect::<Vec<_>>();
 
-    let annotation_name = annotation_name(name);
-    let expected_hash = annotations.get(&annotation_name).cloned();
-    let actual_hash = hash(spec);
+    let modified = !to_remove.is_empty();
 
-    match expected_hash {
-      Some(expected) if expected == actual_hash => SecretStatus::Matches,
-      Some(_) => SecretStatus::Outdated,
-      None => SecretStatus::Missing,
+    for annotation in to_remove {
+      let name = &annotation[ANNOTATION_PREFIX.len()..];
+      info!("removing secret entry {}", name);
+      annotations.remove(&generated_annotation_name(name));
+      annotations.remove(&description_annotation_name(name));
+      annotations.remove(&expires_annotation_name(name));
+      annotations.remove(&expiry_warned_annotation_name(name));
+      annotations.remove(&annotation);
     }
 
-    // let value = ByteString(conf.generate().into_bytes());
-    // annotations.insert(annotation_name, actual_hash);
-    // data.insert(name.into(), value);
+    modified
   }
 
-  fn set_secret(&mut self, name: &str, spec: &super::AutoSecretType) {
-    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
-    let data = self.data.get_or_insert_with(Default::default);
-    let value = ByteString(spec.generate().into_bytes());
-    let annotation_name = annotation_name(name);
-    let actual_hash = hash(spec);
+  /// Keys that belong to the secrets generated by the controller, which are the keys that have a hash annotation and
+  /// their companion keys `<name>.<suffix>`, and the previous values that are kept.
+  fn managed_keys(&self) -> HashSet<String> {
+    let annotations = self.metadata.annotations.iter().flatten();
+    let entries = annotations
+      .clone()
+      .filter_map(|(annotation, _)| annotation.strip_prefix(ANNOTATION_PREFIX))
+      .collect::<Vec<_>>();
+    let previous = annotations
+      .filter_map(|(annotation, _)| annotation.strip_prefix(PREVIOUS_ANNOTATION_PREFIX))
+      .collect::<HashSet<_>>();
 
-    annotations.insert(annotation_name, actual_hash);
-    data.insert(name.into(), value);
+    let keys = self.data.iter().flatten().map(|(key, _)| key);
+    keys
+      .filter(|key| {
+        let entry = entries.iter().any(|entry| {
+          let companion = key.strip_prefix(entry).is_some_and(|suffix| suffix.starts_with('.'));
+          key == entry || companion
+        });
+        entry || previous.contains(key.as_str())
+      })
+      .cloned()
+      .collect()
   }
 
-  async fn apply(self, client: Client) -> Result<(), ControllerError> {
-    let namespace = self.metadata.namespace.clone().expect("secret must have namespace");
-    let name = self.metadata.name.clone().expect("secret must have name");
-    let secret_api = Api::<Secret>::namespaced(client, &namespace);
+  fn set_type(&mut self, secret_type: &str) -> bool {
+    let changed = matches!(&self.type_, Some(existing) if existing != secret_type);
+    self.type_ = Some(secret_type.to_owned());
+    changed
+  }
 
-    patch_secret(secret_api, &name, self).await
+  /// Adds the labels and annotations of `template`. The secret is applied with only the annotations of the controller,
+  /// so labels and annotations that are removed from the template are removed from the secret by server-side apply,
+  /// without touching the ones that are set by others.
+  fn set_template(&mut self, template: &SecretTemplate) {
+    if !template.labels.is_empty() {
+      let labels = self.metadata.labels.get_or_insert_with(Default::default);
+      labels.extend(template.labels.clone());
+    }
+
+    if !template.annotations.is_empty() {
+      let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+      annotations.extend(template.annotations.clone());
+    }
   }
-}
 
-const ANNOTATION_PREFIX: &str = "autosecrets.webstep.no/";
+  /// Marks the secret as immutable or not, and returns whether it has to be recreated for that, as kubernetes doesn't
+  /// allow changing the data of an immutable secret compared to the `live_data`, or making it mutable again.
+  fn set_immutable(&mut self, immutable: bool, live_data: Option<&BTreeMap<String, ByteString>>) -> bool {
+    let recreate = self.immutable == Some(true) && (!immutable || self.data.as_ref() != live_data);
+    self.immutable = immutable.then_some(true);
+    recreate
+  }
 
-fn annotation_name(name: &str) -> String {
-  format!("{ANNOTATION_PREFIX}{name}")
-}
+  /// Records the rotation generation the secrets were generated for, so that it can be seen whether a bump was applied.
+  fn set_rotation_generation(&mut self, rotation_generation: Option<u64>) {
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    match rotation_generation {
+      Some(generation) => annotations.insert(ROTATION_GENERATION_ANNOTATION.to_owned(), generation.to_string()),
+      None => annotations.remove(ROTATION_GENERATION_ANNOTATION),
+    };
+  }
 
-#[tracing::instrument(skip_all, fields(secret.name = name))]
-async fn get_secret(secret_api: &Api<Secret>, name: &str) -> Result<Option<Secret>, ControllerError> {
-  secret_api.get_opt(name).await.map_err(ControllerError::SecretGetFailed)
-}
+  /// Keys whose values were changed since the secret was read, leaving out the keys that were added or removed.
+  fn rotated_keys(&self, live_data: Option<&BTreeMap<String, ByteString>>) -> Vec<String> {
+    let data = self.data.iter().flatten();
+    data
+      .filter(|(key, value)| {
+        live_data
+          .and_then(|live| live.get(*key))
+          .is_some_and(|live| live != *value)
+      })
+      .map(|(key, _)| key.clone())
+      .collect()
+  }
 
-#[tracing::instrument(skip_all, fields(secret.name = name))]
-async fn patch_secret(secret_api: Api<Secret>, name: &str, secret: Secret) -> Result<(), ControllerError> {
-  secret_api
-    .patch(
+  /// Status of the generated keys of the secret, read from its annotations. Keys that aren't generated yet are left
+  /// out.
+  fn key_statuses(&self, secrets: &[(String, super::SecretSpec)]) -> BTreeMap<String, super::KeyStatus> {
+    let annotations = self.metadata.annotations.iter().flatten().collect::<BTreeMap<_, _>>();
+    secrets
+      .iter()
+      .filter_map(|(name, spec)| {
+        let hash = annotations.get(&annotation_name(name))?;
+        let last_generated_time = annotations
+          .get(&generated_annotation_name(name))
+          .and_then(|generated| DateTime::parse_from_rfc3339(generated).ok())
+          .map(|generated| Time(generated.with_timezone(&Utc)));
+
+        let status = super::KeyStatus {
+          last_generated_time,
+          hash: hash.to_string(),
+          type_: spec.generator_type(),
+        };
+        Some((name.clone(), status))
+      })
+      .collect()
+  }
+
+  /// Time of the restart of the workloads that is yet to be done, if any.
+  fn pending_restart(&self) -> Option<String> {
+    self
+      .metadata
+      .annotations
+      .as_ref()?
+      .get(PENDING_RESTART_ANNOTATION)
+      .cloned()
+  }
+
+  fn set_pending_restart(&mut self, restarted_at: Option<&str>) {
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    match restarted_at {
+      Some(restarted_at) => annotations.insert(PENDING_RESTART_ANNOTATION.to_owned(), restarted_at.to_owned()),
+      None => annotations.remove(PENDING_RESTART_ANNOTATION),
+    };
+  }
+
+  fn set_description(&mut self, name: &str, spec: &super::SecretSpec) {
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    let description_annotation_name = description_annotation_name(name);
+    match spec.description() {
+      Some(description) => annotations.insert(description_annotation_name, description.to_owned()),
+      None => annotations.remove(&description_annotation_name),
+    };
+  }
+
+  /// Forgets the hashes of the secret `name`, so that it's generated anew by [`SecretExt::set_secret`] even if its
+  /// spec is unchanged.
+  fn force_rotation(&mut self, name: &str) {
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    if let Some(hash) = annotations.get_mut(&annotation_name(name)) {
+      hash.clear();
+    }
+  }
+
+  /// Writes the `keys` to the env file. The env file is tracked like a generated key, with the hash of its content, so
+  /// that it's removed along with the other keys that aren't in the spec.
+  fn set_env_file(&mut self, env_file: &EnvFileSpec, keys: &[String]) -> Result<(), ControllerError> {
+    let data = self.data.get_or_insert_with(Default::default);
+    let values = keys
+      .iter()
+      .filter_map(|key| Some((key.as_str(), data.get(key)?.0.as_slice())));
+    let content = env_file
+      .render(values)
+      .map_err(|key| ControllerError::InvalidEnvFileValue(key.to_owned()))?;
+
+    set_bundle(self, &env_file.key, content);
+    Ok(())
+  }
+
+  fn set_json_file(&mut self, json_file: &JsonFileSpec, keys: &[String]) {
+    let data = self.data.get_or_insert_with(Default::default);
+    let values = keys
+      .iter()
+      .filter_map(|key| Some((key.as_str(), data.get(key)?.0.as_slice())));
+    let content = json_file.render(values);
+    set_bundle(self, &json_file.key, content);
+  }
+
+  /// Checks that the data and annotations of the secret fit in the size kubernetes allows for a secret, so that it
+  /// isn't applied over and over again only to be rejected.
+  fn check_size(&self) -> Result<(), ControllerError> {
+    let data = self.data.iter().flatten();
+    let mut sizes = data
+      .map(|(key, value)| (key.as_str(), key.len() + value.0.len()))
+      .collect::<Vec<_>>();
+    let annotations = self.metadata.annotations.iter().flatten();
+    let annotations_size = annotations.map(|(key, value)| key.len() + value.len()).sum::<usize>();
+
+    let size = sizes.iter().map(|(_, size)| size).sum::<usize>() + annotations_size;
+    if size <= MAX_SECRET_SIZE {
+      return Ok(());
+    }
+
+    sizes.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let keys = sizes
+      .iter()
+      .take(3)
+      .map(|(key, size)| format!("{key} ({size} bytes)"))
+      .collect::<Vec<_>>();
+
+    Err(ControllerError::SecretTooLarge {
+      size,
+      limit: MAX_SECRET_SIZE,
+      keys: keys.join(", "),
+    })
+  }
+
+  fn secret_status(&self, name: &str, spec: &super::SecretSpec, inputs: &Inputs) -> SecretStatus {
+    let annotations = match self.metadata.annotations.as_ref() {
+      None => return SecretStatus::Missing,
+      Some(v) => v,
+    };
+
+    let annotation_name = annotation_name(name);
+    let expected_hash = annotations.get(&annotation_name).cloned();
+    let actual_hash = hash(spec, inputs, &dependency_values(self, spec));
+
+    let data = self.data.as_ref();
+    let has_outputs = spec
+      .outputs(name)
+      .iter()
+      .all(|output| data.map(|d| d.contains_key(output)).unwrap_or(false));
+
+    match expected_hash {
+      Some(_) if !has_outputs => SecretStatus::Missing,
+      Some(expected) if expected != actual_hash => SecretStatus::Outdated,
+      Some(_) if self.rotation_in(name, spec) == Some(Duration::ZERO) => SecretStatus::Expired,
+      Some(_) if self.renewal_in(spec) == Some(Duration::ZERO) => SecretStatus::Renewing,
+      Some(_) => SecretStatus::Matches,
+      None => SecretStatus::Missing,
+    }
+
+    // let value = ByteString(conf.generate().into_bytes());
+    // annotations.insert(annotation_name, actual_hash);
+    // data.insert(name.into(), value);
+  }
+
+  /// Time until the secret `name` is due to be rotated, if it is rotated periodically. Secrets without a recorded
+  /// generation time are due immediately.
+  fn rotation_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration> {
+    if !spec.rotates() {
+      return None;
+    }
+
+    let generated = self
+      .metadata
+      .annotations
+      .as_ref()
+      .and_then(|annotations| annotations.get(&generated_annotation_name(name)))
+      .and_then(|generated| DateTime::parse_from_rfc3339(generated).ok());
+
+    // a schedule that no longer fires is never due
+    let rotation = match generated {
+      Some(generated) => spec.next_rotation(generated.with_timezone(&Utc))?,
+      None => return Some(Duration::ZERO),
+    };
+
+    Some((rotation - Utc::now()).to_std().unwrap_or_default())
+  }
+
+  /// Time until the value of the secret `name` reaches its minimum age, while it's younger than that. Values without a
+  /// recorded generation time may be regenerated right away.
+  fn cooldown_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration> {
+    let min_age = spec.min_age()?;
+    let generated = self
+      .metadata
+      .annotations
+      .as_ref()?
+      .get(&generated_annotation_name(name))
+      .and_then(|generated| DateTime::parse_from_rfc3339(generated).ok())?;
+
+    let age = (Utc::now() - generated.with_timezone(&Utc))
+      .to_std()
+      .unwrap_or_default();
+    Some(min_age.saturating_sub(age)).filter(|cooldown| !cooldown.is_zero())
+  }
+
+  /// Whether updating the secret `name` generates a new value, rather than keeping the existing one because the
+  /// parameters it was generated from are unchanged.
+  fn regenerates(&self, name: &str, spec: &super::SecretSpec) -> bool {
+    let expired = self.rotation_in(name, spec) == Some(Duration::ZERO) || self.renewal_in(spec) == Some(Duration::ZERO);
+    let expected_value_hash = self
+      .metadata
+      .annotations
+      .as_ref()
+      .and_then(|annotations| annotations.get(&annotation_name(name)))
+      .and_then(|hash| hash.split_once('.'))
+      .map(|(_, value_hash)| value_hash);
+
+    match value_hash(spec) {
+      Some(value_hash) => expired || expected_value_hash != Some(&value_hash),
+      None => true,
+    }
+  }
+
+  /// Time until the certificate of the secret is due to be renewed, if it has one.
+  fn renewal_in(&self, spec: &super::SecretSpec) -> Option<Duration> {
+    let renew_before = spec.renew_before()?;
+    let certificate = self.data.as_ref()?.get(&spec.certificate_key()?)?;
+    let not_after = super::x509::not_after(&certificate.0)?;
+    let renewal = not_after.checked_sub(renew_before).unwrap_or(std::time::UNIX_EPOCH);
+    Some(renewal.duration_since(std::time::SystemTime::now()).unwrap_or_default())
+  }
+
+  /// Time the secret `name` expires, which is when its certificate expires or when it's due to be rotated, whichever
+  /// is first, along with whether it expires without being rotated.
+  fn expiry(&self, name: &str, spec: &super::SecretSpec) -> Option<(DateTime<Utc>, bool)> {
+    let annotations = self.metadata.annotations.as_ref();
+    let rotation = annotations
+      .and_then(|annotations| annotations.get(&generated_annotation_name(name)))
+      .and_then(|generated| DateTime::parse_from_rfc3339(generated).ok())
+      .and_then(|generated| spec.next_rotation(generated.with_timezone(&Utc)));
+
+    let data = self.data.as_ref();
+    let certificate = spec
+      .certificate_key()
+      .and_then(|key| data?.get(&key))
+      .and_then(|certificate| super::x509::not_after(&certificate.0))
+      .map(DateTime::<Utc>::from);
+
+    match (certificate, rotation) {
+      (Some(certificate), Some(rotation)) if certificate < rotation => Some((certificate, true)),
+      (Some(certificate), None) => Some((certificate, true)),
+      (_, Some(rotation)) => Some((rotation, false)),
+      (None, None) => None,
+    }
+  }
+
+  /// Records the time the secret `name` expires in an annotation.
+  fn set_expiry(&mut self, name: &str, spec: &super::SecretSpec) {
+    let expiry = self.expiry(name, spec);
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    match expiry {
+      Some((expires, _)) => annotations.insert(expires_annotation_name(name), timestamp(expires)),
+      None => annotations.remove(&expires_annotation_name(name)),
+    };
+  }
+
+  /// Returns the time the secret `name` expires if it's within `threshold` of expiring without being rotated first,
+  /// and it hasn't been warned about yet. Secrets that are rotated when they expire are never warned about, as the
+  /// controller replaces them in time.
+  fn expiry_warning(&mut self, name: &str, spec: &super::SecretSpec, threshold: Duration) -> Option<DateTime<Utc>> {
+    let expiry = self.expiry(name, spec);
+    let warning_in = self.expiry_warning_in(name, spec, threshold);
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    match expiry {
+      Some((expires, true)) if warning_in == Some(Duration::ZERO) => {
+        annotations.insert(expiry_warned_annotation_name(name), timestamp(expires));
+        Some(expires)
+      }
+      Some((_, true)) => None,
+      _ => {
+        annotations.remove(&expiry_warned_annotation_name(name));
+        None
+      }
+    }
+  }
+
+  /// Time until the secret `name` is due to be warned about, if it expires without being rotated first and it hasn't
+  /// been warned about for the time it expires.
+  fn expiry_warning_in(&self, name: &str, spec: &super::SecretSpec, threshold: Duration) -> Option<Duration> {
+    let expires = match self.expiry(name, spec)? {
+      (expires, true) => expires,
+      (_, false) => return None,
+    };
+
+    let annotations = self.metadata.annotations.as_ref();
+    let warned = annotations.and_then(|annotations| annotations.get(&expiry_warned_annotation_name(name)));
+    if warned == Some(&timestamp(expires)) {
+      return None;
+    }
+
+    let threshold = chrono::Duration::from_std(threshold).ok()?;
+    Some((expires - threshold - Utc::now()).to_std().unwrap_or_default())
+  }
+
+  /// Time until the first of the previous values of the secret `name` is due to be removed, if they are only kept for a
+  /// while.
+  fn previous_expires_in(&self, name: &str, spec: &super::SecretSpec) -> Option<Duration> {
+    let ttl = spec.previous_ttl()?;
+    let annotations = self.metadata.annotations.as_ref()?;
+    spec
+      .previous_keys(name)
+      .iter()
+      .filter_map(|(_, previous)| annotations.get(&previous_annotation_name(previous)))
+      .filter_map(|kept| DateTime::parse_from_rfc3339(kept).ok())
+      .map(|kept| {
+        let age = (Utc::now() - kept.with_timezone(&Utc)).to_std().unwrap_or_default();
+        ttl.saturating_sub(age)
+      })
+      .min()
+  }
+
+  fn remove_expired_previous(&mut self, name: &str, spec: &super::SecretSpec) {
+    let ttl = match spec.previous_ttl() {
+      Some(ttl) => ttl,
+      None => return,
+    };
+
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    let data = self.data.get_or_insert_with(Default::default);
+    for (_, previous) in spec.previous_keys(name) {
+      let kept = annotations
+        .get(&previous_annotation_name(&previous))
+        .and_then(|kept| DateTime::parse_from_rfc3339(kept).ok());
+      let age = kept.map(|kept| (Utc::now() - kept.with_timezone(&Utc)).to_std().unwrap_or_default());
+      if age.is_some_and(|age| age >= ttl) {
+        info!("removing previous value {} as it is older than its ttl", previous);
+        annotations.remove(&previous_annotation_name(&previous));
+        data.remove(&previous);
+      }
+    }
+  }
+
+  async fn set_secret(&mut self, name: &str, spec: &super::SecretSpec, inputs: &Inputs) -> Result<(), ControllerError> {
+    let expired = self.rotation_in(name, spec) == Some(Duration::ZERO) || self.renewal_in(spec) == Some(Duration::ZERO);
+    let values = dependency_values(self, spec);
+
+    // the secret can only be generated once the values it's generated from exist
+    if let Some(reference) = spec.references().into_iter().find(|r| !inputs.contains_key(r)) {
+      return Err(ControllerError::MissingReference(reference.secret, reference.key));
+    }
+
+    if let Some(key) = spec.dependencies().into_iter().find(|key| !values.contains_key(key)) {
+      return Err(ControllerError::MissingDependency(name.to_owned(), key));
+    }
+
+    let annotations = self.metadata.annotations.get_or_insert_with(Default::default);
+    let data = self.data.get_or_insert_with(Default::default);
+    let annotation_name = annotation_name(name);
+    let actual_hash = hash(spec, inputs, &values);
+
+    // the existing value is kept if the parameters it was generated from are unchanged
+    let expected_value_hash = annotations
+      .get(&annotation_name)
+      .and_then(|hash| hash.split_once('.'))
+      .map(|(_, value_hash)| value_hash);
+    let present = spec
+      .outputs(name)
+      .into_iter()
+      .filter_map(|key| Some((key.clone(), data.get(&key)?.0.clone())))
+      .collect::<Values>();
+
+    // keys that were in the secret before it was generated by the controller are adopted as they are, or reused by
+    // generators that can keep their value when only some of the keys are there
+    let import = spec.import_if_present() && !annotations.contains_key(&annotation_name);
+    if import && present.len() == spec.outputs(name).len() {
+      info!("importing existing keys of secret {}", name);
+      annotations.insert(generated_annotation_name(name), timestamp(Utc::now()));
+      annotations.insert(annotation_name, actual_hash);
+      return Ok(());
+    }
+
+    let existing = match value_hash(spec) {
+      Some(value_hash) if !expired && expected_value_hash == Some(&value_hash) => Some(present),
+      Some(_) if import => Some(present),
+      _ if spec.reuses_key() => Some(present),
+      _ => None,
+    };
+
+    // certificates that keep their key are reissued with it, but they are still new values
+    let reused = !spec.reuses_key()
+      && existing
+        .as_ref()
+        .is_some_and(|existing| existing.contains_key(&spec.outputs(name)[0]));
+    let outputs = generate(name, spec, inputs, &values, existing).await?;
+
+    // the generation time is kept while the value is reused, as that's what the rotation interval and the minimum age
+    // apply to
+    let generated_annotation_name = generated_annotation_name(name);
+    if !reused || !annotations.contains_key(&generated_annotation_name) {
+      annotations.insert(generated_annotation_name, timestamp(Utc::now()));
+    }
+
+    // the values that are replaced are kept until the next time they are regenerated, or until their ttl is up
+    for (output, previous) in spec.previous_keys(name) {
+      match data.get(&output) {
+        Some(value) if outputs.get(&output) != Some(&value.0) => {
+          let value = value.clone();
+          data.insert(previous.clone(), value);
+          annotations.insert(previous_annotation_name(&previous), timestamp(Utc::now()));
+        }
+        _ => {}
+      }
+    }
+
+    annotations.insert(annotation_name, actual_hash);
+    for (output, value) in outputs {
+      data.insert(output, ByteString(value));
+    }
+
+    Ok(())
+  }
+
+  async fn delete(&self, client: Client) -> Result<(), ControllerError> {
+    let namespace = self.metadata.namespace.clone().expect("secret must have namespace");
+    let name = self.metadata.name.clone().expect("secret must have name");
+    let secret_api = Api::<Secret>::namespaced(client, &namespace);
+
+    delete_secret(secret_api, &name).await
+  }
+
+  /// Removes `owner` from the owners of the secret, so that it isn't garbage collected along with it.
+  async fn orphan(&self, client: Client, owner: &super::AutoSecret) -> Result<(), ControllerError> {
+    let namespace = self.metadata.namespace.clone().expect("secret must have namespace");
+    let name = self.metadata.name.clone().expect("secret must have name");
+    let secret_api = Api::<Secret>::namespaced(client, &namespace);
+
+    let owner_references = self
+      .metadata
+      .owner_references
+      .iter()
+      .flatten()
+      .filter(|owner_reference| owner.metadata.uid.as_ref() != Some(&owner_reference.uid))
+      .collect::<Vec<_>>();
+
+    orphan_secret(secret_api, &name, owner_references).await
+  }
+
+  /// Orphans the secret and removes the annotations of the controller from it, so that it's left as a plain secret. A
+  /// secret that's already gone has nothing left to release.
+  async fn release(&self, client: Client, owner: &super::AutoSecret) -> Result<(), ControllerError> {
+    let namespace = self.metadata.namespace.clone().expect("secret must have namespace");
+    let name = self.metadata.name.clone().expect("secret must have name");
+    let secret_api = Api::<Secret>::namespaced(client, &namespace);
+
+    let patch = release_patch(self, owner);
+    match secret_api
+      .patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+      .await
+    {
+      Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+      result => result.map(|_| ()).map_err(ControllerError::SecretApplyFailed),
+    }
+  }
+
+  async fn apply(self, client: Client) -> Result<(), ControllerError> {
+    let namespace = self.metadata.namespace.clone().expect("secret must have namespace");
+    let name = self.metadata.name.clone().expect("secret must have name");
+    let secret_api = Api::<Secret>::namespaced(client, &namespace);
+
+    patch_secret(secret_api, &name, self).await
+  }
+
+  /// Applies the secret, and recreates it if kubernetes rejects the change as the secret is immutable, for instance
+  /// when it was changed since it was read.
+  async fn apply_or_recreate(self, client: Client) -> Result<(), ControllerError> {
+    match self.clone().apply(client.clone()).await {
+      Err(e) if e.is_immutable_field() => {
+        warn!(
+          "recreating secret {} as it can't be updated: {}",
+          self.metadata.name.as_deref().unwrap_or_default(),
+          e
+        );
+        self.delete(client.clone()).await?;
+        self.apply(client).await
+      }
+      result => result,
+    }
+  }
+}
+
+const ANNOTATION_PREFIX: &str = "autosecrets.webstep.no/";
+const GENERATED_ANNOTATION_PREFIX: &str = "generated.autosecrets.webstep.no/";
+const DESCRIPTION_ANNOTATION_PREFIX: &str = "description.autosecrets.webstep.no/";
+const PREVIOUS_ANNOTATION_PREFIX: &str = "previous.autosecrets.webstep.no/";
+const EXPIRES_ANNOTATION_PREFIX: &str = "expires.autosecrets.webstep.no/";
+const EXPIRY_WARNED_ANNOTATION_PREFIX: &str = "expiry-warned.autosecrets.webstep.no/";
+const TARGET_ANNOTATION: &str = "target.autosecrets.webstep.no/source";
+const HISTORY_ANNOTATION: &str = "history.autosecrets.webstep.no/source";
+const OWNER_ANNOTATION: &str = "owner.autosecrets.webstep.no/autosecret";
+const ROTATION_GENERATION_ANNOTATION: &str = "generation.autosecrets.webstep.no/rotation";
+const ROTATE_ANNOTATION: &str = "autosecrets.webstep.no/rotate";
+const ROTATED_AT_ANNOTATION: &str = "autosecrets.webstep.no/rotated-at";
+const PENDING_RESTART_ANNOTATION: &str = "restart.autosecrets.webstep.no/pending";
+const RESTARTED_AT_ANNOTATION: &str = "autosecrets.webstep.no/restartedAt";
+const REPORTER: &str = "auto-secret";
+const DEFAULT_SECRET_TYPE: &str = "Opaque";
+// named after the deletion policy it was first added for, as renaming it would leave existing autosecrets with a
+// finalizer that is never removed
+pub const FINALIZER: &str = "autosecrets.webstep.no/retain";
+const MAX_KEY_LENGTH: usize = 253;
+const MAX_SECRET_SIZE: usize = 1024 * 1024;
+
+fn annotation_name(name: &str) -> String {
+  format!("{ANNOTATION_PREFIX}{name}")
+}
+
+/// Adds the defaults of the autosecret `spec` to its secrets.
+pub fn apply_defaults(spec: &mut serde_json::Value) -> Result<(), String> {
+  let defaults = match spec.get("defaults") {
+    Some(defaults) => SecretDefaults::deserialize(defaults).map_err(|e| format!("invalid defaults: {e}"))?,
+    None => return Ok(()),
+  };
+
+  let secrets = spec.get_mut("secrets").and_then(|secrets| secrets.as_object_mut());
+  for (name, secret) in secrets.into_iter().flatten() {
+    *secret = defaults
+      .apply(secret.take())
+      .map_err(|e| format!("invalid secret {name}: {e}"))?;
+  }
+
+  Ok(())
+}
+
+/// Checks that every secret of the autosecret `spec` is valid on its own, so that errors name the secret they are in.
+pub fn check_secrets(spec: &serde_json::Value) -> Result<(), String> {
+  let secrets = spec.get("secrets").and_then(|secrets| secrets.as_object());
+  for (name, secret) in secrets.into_iter().flatten() {
+    <SecretSpec as Deserialize>::deserialize(secret).map_err(|e| format!("invalid secret {name}: {e}"))?;
+  }
+
+  Ok(())
+}
+
+/// Whether `key` can be used as a key of the data of a secret, which kubernetes limits to at most 253 alphanumeric
+/// characters, '-', '_' or '.'.
+fn is_valid_key(key: &str) -> bool {
+  let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+  !key.is_empty() && key.len() <= MAX_KEY_LENGTH && key.chars().all(valid)
+}
+
+/// Schema of the secrets of an autosecret, which has to define at least one secret.
+pub fn secrets_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+  let mut schema = schemars::schema::SchemaObject {
+    instance_type: Some(schemars::schema::InstanceType::Object.into()),
+    ..Default::default()
+  };
+  schema.object().additional_properties = Some(Box::new(SecretSpec::schema(gen)));
+  schema.object().min_properties = Some(1);
+  schemars::schema::Schema::Object(schema)
+}
+
+/// Whether `key` is in one of the namespaces of the annotations the controller uses to track the secrets.
+fn is_reserved_key(key: &str) -> bool {
+  let prefixes = [
+    ANNOTATION_PREFIX,
+    GENERATED_ANNOTATION_PREFIX,
+    DESCRIPTION_ANNOTATION_PREFIX,
+    PREVIOUS_ANNOTATION_PREFIX,
+    EXPIRES_ANNOTATION_PREFIX,
+    EXPIRY_WARNED_ANNOTATION_PREFIX,
+  ];
+  let keys = [
+    TARGET_ANNOTATION,
+    HISTORY_ANNOTATION,
+    OWNER_ANNOTATION,
+    ROTATION_GENERATION_ANNOTATION,
+    PENDING_RESTART_ANNOTATION,
+  ];
+  prefixes.iter().any(|prefix| key.starts_with(prefix)) || keys.contains(&key)
+}
+
+/// Name of the autosecret that wrote `secret`, from its owner annotation.
+fn owner_name(secret: &Secret) -> Option<&String> {
+  secret.metadata.annotations.as_ref()?.get(OWNER_ANNOTATION)
+}
+
+/// Whether `secret` is a history, which keeps the values of the secret named in its history annotation.
+fn is_history(secret: &Secret) -> bool {
+  let annotations = secret.metadata.annotations.as_ref();
+  annotations.is_some_and(|annotations| annotations.contains_key(HISTORY_ANNOTATION))
+}
+
+/// Whether `secret` is a target, which holds copies of the keys of the secret named in its target annotation.
+fn is_target(secret: &Secret) -> bool {
+  let annotations = secret.metadata.annotations.as_ref();
+  annotations.is_some_and(|annotations| annotations.contains_key(TARGET_ANNOTATION))
+}
+
+/// Name of the annotation holding the time the secret `name` was generated, for secrets that are rotated.
+pub fn generated_annotation_name(name: &str) -> String {
+  format!("{GENERATED_ANNOTATION_PREFIX}{name}")
+}
+
+/// Name of the annotation holding the description of the secret `name`.
+fn description_annotation_name(name: &str) -> String {
+  format!("{DESCRIPTION_ANNOTATION_PREFIX}{name}")
+}
+
+fn previous_annotation_name(key: &str) -> String {
+  format!("{PREVIOUS_ANNOTATION_PREFIX}{key}")
+}
+
+fn expires_annotation_name(name: &str) -> String {
+  format!("{EXPIRES_ANNOTATION_PREFIX}{name}")
+}
+
+fn expiry_warned_annotation_name(name: &str) -> String {
+  format!("{EXPIRY_WARNED_ANNOTATION_PREFIX}{name}")
+}
+
+/// Condition of the status of an autosecret of the given `generation`, which transitioned now.
+fn condition(type_: &str, status: bool, reason: &str, message: String, generation: Option<i64>) -> Condition {
+  Condition {
+    type_: type_.to_owned(),
+    status: if status { "True" } else { "False" }.to_owned(),
+    reason: reason.to_owned(),
+    message,
+    last_transition_time: Time(Utc::now()),
+    observed_generation: generation,
+  }
+}
+
+/// Merge patch that removes `owner` from the owners of `secret` along with the annotations of the controller, leaving
+/// the data and the other owners and annotations as they are.
+fn release_patch(secret: &Secret, owner: &super::AutoSecret) -> serde_json::Value {
+  let owner_references = secret
+    .metadata
+    .owner_references
+    .iter()
+    .flatten()
+    .filter(|owner_reference| owner.metadata.uid.as_ref() != Some(&owner_reference.uid))
+    .collect::<Vec<_>>();
+
+  // annotations are removed from a merge patch by setting them to null
+  let annotations = secret
+    .metadata
+    .annotations
+    .iter()
+    .flatten()
+    .filter(|(key, _)| is_reserved_key(key))
+    .map(|(key, _)| (key.clone(), serde_json::Value::Null))
+    .collect::<serde_json::Map<_, _>>();
+
+  serde_json::json!({
+    "metadata": {
+      "ownerReferences": owner_references,
+      "annotations": annotations,
+    },
+  })
+}
+
+/// Merge patch that leaves only the `failed` rotations in the rotation request of `auto_secret`, if that changes the
+/// request. The rotation time is only updated when some of the requested secrets were rotated.
+fn rotation_request_patch(auto_secret: &super::AutoSecret, failed: &[String]) -> Option<serde_json::Value> {
+  let (requested, unknown) = auto_secret.requested_rotations()?;
+  let rotated = requested.iter().any(|name| !failed.contains(name));
+  if !rotated && unknown.is_empty() {
+    return None;
+  }
+
+  let remaining = match failed.is_empty() {
+    true => serde_json::Value::Null,
+    false => failed.join(",").into(),
+  };
+  let mut annotations = serde_json::Map::from_iter([(ROTATE_ANNOTATION.to_owned(), remaining)]);
+  if rotated {
+    annotations.insert(ROTATED_AT_ANNOTATION.to_owned(), timestamp(Utc::now()).into());
+  }
+
+  Some(serde_json::json!({ "metadata": { "annotations": annotations } }))
+}
+
+/// Merge patch of the status from `existing` to `status`. The fields and keys that are left out of the status, like
+/// failed keys that recovered and keys that were removed, are set to null, as a merge patch would keep them otherwise.
+fn status_patch(existing: Option<&super::AutoSecretStatus>, status: &super::AutoSecretStatus) -> serde_json::Value {
+  merge_patch(&serde_json::json!(existing), &serde_json::json!(status))
+}
+
+/// Merge patch that turns the `existing` value into `value`, as described in RFC 7386.
+fn merge_patch(existing: &serde_json::Value, value: &serde_json::Value) -> serde_json::Value {
+  let (existing, value) = match (existing, value) {
+    (serde_json::Value::Object(existing), serde_json::Value::Object(value)) => (existing, value),
+    _ => return value.clone(),
+  };
+
+  let removed = existing
+    .keys()
+    .filter(|key| !value.contains_key(*key))
+    .map(|key| (key.clone(), serde_json::Value::Null));
+  let changed = value
+    .iter()
+    .filter(|(key, value)| existing.get(*key) != Some(value))
+    .map(|(key, value)| match existing.get(key) {
+      Some(existing) => (key.clone(), merge_patch(existing, value)),
+      None => (key.clone(), value.clone()),
+    });
+
+  serde_json::Value::Object(removed.chain(changed).collect())
+}
+
+/// Merge patch that changes the pod template of a workload, which makes kubernetes roll out new pods.
+fn restart_patch(restarted_at: &str) -> serde_json::Value {
+  serde_json::json!({
+    "spec": {
+      "template": {
+        "metadata": {
+          "annotations": { RESTARTED_AT_ANNOTATION: restarted_at },
+        },
+      },
+    },
+  })
+}
+
+/// Formats `time` the way it's stored in annotations.
+pub fn timestamp(time: DateTime<Utc>) -> String {
+  time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[tracing::instrument(skip_all, fields(secret.name = name))]
+async fn get_secret(secret_api: &Api<Secret>, name: &str) -> Result<Option<Secret>, ControllerError> {
+  secret_api.get_opt(name).await.map_err(ControllerError::SecretGetFailed)
+}
+
+#[tracing::instrument(skip_all)]
+async fn list_secrets(secret_api: &Api<Secret>) -> Result<Vec<Secret>, ControllerError> {
+  let secrets = secret_api
+    .list(&ListParams::default())
+    .await
+    .map_err(ControllerError::SecretListFailed)?;
+
+  Ok(secrets.items)
+}
+
+#[tracing::instrument(skip_all, fields(secret.name = name))]
+async fn patch_secret(secret_api: Api<Secret>, name: &str, secret: Secret) -> Result<(), ControllerError> {
+  secret_api
+    .patch(
       name,
       &PatchParams::apply("autosecrets.webstep.no").force(),
       &Patch::Apply(&secret),
@@ -264,17 +1885,130 @@ async fn patch_secret(secret_api: Api<Secret>, name: &str, secret: Secret) -> Re
   Ok(())
 }
 
+#[tracing::instrument(skip_all, fields(secret.name = name))]
+async fn delete_secret(secret_api: Api<Secret>, name: &str) -> Result<(), ControllerError> {
+  secret_api
+    .delete(name, &DeleteParams::default())
+    .await
+    .map_err(ControllerError::SecretDeleteFailed)?;
+
+  Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(secret.name = name))]
+async fn orphan_secret(
+  secret_api: Api<Secret>,
+  name: &str,
+  owner_references: Vec<&OwnerReference>,
+) -> Result<(), ControllerError> {
+  // a merge patch replaces the whole list of owner references, and removes the annotations that are set to null
+  let patch = serde_json::json!({
+    "metadata": {
+      "ownerReferences": owner_references,
+      "annotations": { OWNER_ANNOTATION: null },
+    },
+  });
+  secret_api
+    .patch(name, &PatchParams::default(), &Patch::Merge(&patch))
+    .await
+    .map_err(ControllerError::SecretApplyFailed)?;
+
+  Ok(())
+}
+
+/// Writes a key rendered from other keys. It's tracked like a generated key, with the hash of its content, so that it's
+/// removed along with the other keys that are no longer in the spec.
+fn set_bundle(secret: &mut Secret, key: &str, content: Vec<u8>) {
+  let mut hasher = seahash::SeaHasher::new();
+  content.hash(&mut hasher);
+
+  let annotations = secret.metadata.annotations.get_or_insert_with(Default::default);
+  annotations.insert(annotation_name(key), hex::encode(hasher.finish().to_le_bytes()));
+  let data = secret.data.get_or_insert_with(Default::default);
+  data.insert(key.to_owned(), ByteString(content));
+}
+
 fn remove_secret(annotations: &mut BTreeMap<String, String>, data: &mut BTreeMap<String, ByteString>, name: &str) {
   info!("removing secret {}", name);
   annotations.remove(&annotation_name(name));
+  annotations.remove(&generated_annotation_name(name));
+  annotations.remove(&description_annotation_name(name));
+  annotations.remove(&previous_annotation_name(name));
+  annotations.remove(&expires_annotation_name(name));
+  annotations.remove(&expiry_warned_annotation_name(name));
   data.remove(name);
 }
 
-fn hash(value: &impl Hash) -> String {
+/// Values of the keys of `secret` that `spec` depends on.
+fn dependency_values(secret: &Secret, spec: &super::SecretSpec) -> Values {
+  let data = secret.data.as_ref();
+  spec
+    .dependencies()
+    .into_iter()
+    .filter_map(|key| {
+      let value = data?.get(&key)?.0.clone();
+      Some((key, value))
+    })
+    .collect()
+}
+
+async fn generate(
+  name: &str,
+  spec: &super::SecretSpec,
+  inputs: &Inputs,
+  values: &Values,
+  existing: Option<Values>,
+) -> Result<BTreeMap<String, Vec<u8>>, ControllerError> {
+  // generating some secrets (like large rsa keys) takes a while, so it's done outside of the async executor
+  let (name, spec, inputs, values) = (name.to_owned(), spec.clone(), inputs.clone(), values.clone());
+  tokio::task::spawn_blocking(move || {
+    spec
+      .generate(&name, &inputs, &values, existing.as_ref())
+      .map_err(|e| ControllerError::GenerateFailed(name, e))
+  })
+  .await
+  .expect("secret generation panicked")
+}
+
+fn hash(spec: &super::SecretSpec, inputs: &Inputs, values: &Values) -> String {
   let mut hasher = seahash::SeaHasher::new();
-  value.hash(&mut hasher);
+  spec.hash(&mut hasher);
+  // the values read from other secrets and keys are part of the hash, so that changing them regenerates the secret
+  for reference in spec.references() {
+    inputs.get(&reference).hash(&mut hasher);
+  }
+
+  for key in spec.dependencies() {
+    values.get(&key).hash(&mut hasher);
+  }
+
   let value = hasher.finish();
-  hex::encode(value.to_le_bytes())
+  let hash = hex::encode(value.to_le_bytes());
+  match value_hash(spec) {
+    Some(value_hash) => format!("{hash}.{value_hash}"),
+    None => hash,
+  }
+}
+
+/// Hash of the parameters the generated value of `spec` depends on, for specs that keep their value when only their
+/// other parameters change.
+fn value_hash(spec: &super::SecretSpec) -> Option<String> {
+  let params = spec.value_params()?;
+  let mut hasher = seahash::SeaHasher::new();
+  params.hash(&mut hasher);
+
+  // bumping the revision regenerates the value, even though its parameters are unchanged
+  if let Some(revision) = spec.revision() {
+    revision.hash(&mut hasher);
+  }
+
+  // and so does bumping the rotation generation of the autosecret
+  if let Some(rotation_generation) = spec.rotation_generation() {
+    "rotationGeneration".hash(&mut hasher);
+    rotation_generation.hash(&mut hasher);
+  }
+
+  Some(hex::encode(hasher.finish().to_le_bytes()))
 }
 
 #[derive(Debug, Error)]
@@ -282,9 +2016,826 @@ pub enum ControllerError {
   #[error("Failed to get secret: {0}")]
   SecretGetFailed(#[source] kube::Error),
 
+  #[error("Failed to list secrets: {0}")]
+  SecretListFailed(#[source] kube::Error),
+
   #[error("Failed to apply secret: {0}")]
   SecretApplyFailed(#[source] kube::Error),
 
+  #[error("Failed to delete secret: {0}")]
+  SecretDeleteFailed(#[source] kube::Error),
+
+  #[error("Failed to update finalizers: {0}")]
+  FinalizerUpdateFailed(#[source] kube::Error),
+
+  #[error("Failed to update annotations: {0}")]
+  AnnotationUpdateFailed(#[source] kube::Error),
+
+  #[error("Failed to update status: {0}")]
+  StatusUpdateFailed(#[source] kube::Error),
+
   #[error("MissingObjectKey: {0}")]
   MissingObjectKey(&'static str),
+
+  #[error("Referenced secret {0} doesn't exist, or doesn't contain the key {1}")]
+  MissingReference(String, String),
+
+  #[error("Secret {0} depends on the key {1}, which isn't written by any other secret")]
+  MissingDependency(String, String),
+
+  #[error("Secrets have a circular dependency: {0}")]
+  CircularDependency(String),
+
+  #[error("Failed to generate secret {0}: {1}")]
+  GenerateFailed(String, #[source] GenerateError),
+
+  #[error("Secret {0} requires a secret of type {1}, but secret {2} requires type {3}")]
+  ConflictingSecretTypes(String, &'static str, String, &'static str),
+
+  #[error("Secret {0} requires a secret of type {1}, but the secret type is set to {2}")]
+  SecretTypeMismatch(String, &'static str, String),
+
+  #[error("Secrets of type {0} require the key {1}")]
+  MissingSecretTypeKey(String, &'static str),
+
+  #[error("The secret template can't set {0}, as it is reserved for the controller")]
+  ReservedTemplateKey(String),
+
+  #[error("The secret {0} is written more than once, either as several targets or as the managed secret")]
+  DuplicateTarget(String),
+
+  #[error("Target {0} copies the key {1}, which isn't written by any secret")]
+  MissingTargetKey(String, String),
+
+  #[error("Keys are written by more than one secret: {0}")]
+  ConflictingOutputs(String),
+
+  #[error("The key {0} isn't a valid key of a secret, which must consist of alphanumeric characters, '-', '_' or '.'")]
+  InvalidKeyName(String),
+
+  #[error("The {0} includes the key {1}, which isn't written by any secret")]
+  MissingBundleKey(&'static str, String),
+
+  #[error("The env file writes the variable {0} for both {1} and {2}")]
+  ConflictingEnvName(String, String, String),
+
+  #[error("The key {0} can't be written to the env file, as it isn't valid UTF-8")]
+  InvalidEnvFileValue(String),
+
+  #[error("The history secret can't be named {0}: {1}")]
+  InvalidHistoryName(String, String),
+
+  #[error("Failed to restart {0}: {1}")]
+  RestartFailed(String, #[source] kube::Error),
+
+  #[error("Failed to generate secrets {0}, the other secrets are applied")]
+  SecretsFailed(String),
+
+  #[error("The autosecret doesn't define any secrets")]
+  NoSecrets,
+
+  #[error("The secret is {size} bytes, which is more than the {limit} bytes allowed, the largest keys being {keys}")]
+  SecretTooLarge { size: usize, limit: usize, keys: String },
+}
+
+impl From<kube::runtime::finalizer::Error<ControllerError>> for ControllerError {
+  fn from(error: kube::runtime::finalizer::Error<ControllerError>) -> Self {
+    use kube::runtime::finalizer::Error;
+    match error {
+      Error::ApplyFailed(e) | Error::CleanupFailed(e) => e,
+      Error::AddFinalizer(e) | Error::RemoveFinalizer(e) => ControllerError::FinalizerUpdateFailed(e),
+      Error::UnnamedObject => ControllerError::MissingObjectKey(".metadata.name"),
+    }
+  }
+}
+
+impl ControllerError {
+  /// Whether the error is caused by the spec of the autosecret alone, so that retrying won't help before it changes.
+  pub fn is_permanent(&self) -> bool {
+    matches!(
+      self,
+      ControllerError::CircularDependency(_)
+        | ControllerError::ConflictingSecretTypes(..)
+        | ControllerError::SecretTypeMismatch(..)
+        | ControllerError::MissingSecretTypeKey(..)
+        | ControllerError::ReservedTemplateKey(_)
+        | ControllerError::DuplicateTarget(_)
+        | ControllerError::MissingTargetKey(..)
+        | ControllerError::ConflictingOutputs(_)
+        | ControllerError::InvalidKeyName(_)
+        | ControllerError::MissingBundleKey(..)
+        | ControllerError::ConflictingEnvName(..)
+        | ControllerError::InvalidHistoryName(..)
+        | ControllerError::NoSecrets
+        | ControllerError::SecretTooLarge { .. }
+    )
+  }
+
+  /// Reason the reconciliation failed, as it's given in the conditions of the status.
+  pub fn reason(&self) -> &'static str {
+    match self {
+      ControllerError::MissingReference(..) => "MissingReference",
+      ControllerError::MissingDependency(..) => "MissingDependency",
+      ControllerError::GenerateFailed(..) => "GenerateFailed",
+      ControllerError::SecretsFailed(_) => "SecretsFailed",
+      _ if self.is_permanent() => "InvalidSpec",
+      _ => "ReconcileFailed",
+    }
+  }
+
+  /// Whether the secret couldn't be applied as it changes a field that can't be changed, like the data of an immutable
+  /// secret or the type of a secret.
+  fn is_immutable_field(&self) -> bool {
+    matches!(
+      self,
+      ControllerError::SecretApplyFailed(kube::Error::Api(response))
+        if response.code == 422 && response.message.contains("immutable")
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{AutoSecret, SecretSpec};
+
+  fn auto_secret(annotations: serde_json::Value) -> AutoSecret {
+    serde_json::from_value(serde_json::json!({
+      "apiVersion": "webstep.no/v1alpha1",
+      "kind": "AutoSecret",
+      "metadata": { "name": "app", "namespace": "default", "uid": "uid", "annotations": annotations },
+      "spec": { "secrets": { "a": "password", "b": "password", "c": "uuid" } },
+    }))
+    .unwrap()
+  }
+
+  fn spec(value: serde_json::Value) -> SecretSpec {
+    serde_json::from_value(value).unwrap()
+  }
+
+  /// Records the value of `name` as generated `ago`.
+  fn set_generated(secret: &mut Secret, name: &str, ago: Duration) {
+    let generated = Utc::now() - chrono::Duration::from_std(ago).unwrap();
+    let annotations = secret.metadata.annotations.get_or_insert_with(Default::default);
+    annotations.insert(generated_annotation_name(name), timestamp(generated));
+  }
+
+  #[test]
+  fn rotation_request_is_cleared_once_rotated() {
+    let resource = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: "a,b" }));
+    let patch = rotation_request_patch(&resource, &[]).unwrap();
+    let annotations = &patch["metadata"]["annotations"];
+    assert!(annotations[ROTATE_ANNOTATION].is_null());
+    assert!(annotations[ROTATED_AT_ANNOTATION].is_string());
+  }
+
+  #[test]
+  fn failed_rotations_are_kept_in_the_request() {
+    let resource = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: "a,b" }));
+    let patch = rotation_request_patch(&resource, &["b".to_owned()]).unwrap();
+    assert_eq!(patch["metadata"]["annotations"][ROTATE_ANNOTATION], "b");
+  }
+
+  #[test]
+  fn failed_request_leaves_the_autosecret_unchanged() {
+    let resource = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: "a,b" }));
+    let failed = ["a".to_owned(), "b".to_owned()];
+    assert_eq!(rotation_request_patch(&resource, &failed), None);
+
+    let resource = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: "all" }));
+    let failed = ["a".to_owned(), "b".to_owned(), "c".to_owned()];
+    assert_eq!(rotation_request_patch(&resource, &failed), None);
+  }
+
+  #[test]
+  fn unknown_rotations_are_dropped_without_a_rotation_time() {
+    let resource = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: "a,missing" }));
+    let patch = rotation_request_patch(&resource, &["a".to_owned()]).unwrap();
+    let annotations = &patch["metadata"]["annotations"];
+    assert_eq!(annotations[ROTATE_ANNOTATION], "a");
+    assert!(annotations.get(ROTATED_AT_ANNOTATION).is_none());
+  }
+
+  #[test]
+  fn shorthand_and_object_form_are_the_same_secret() {
+    let shorthand: BTreeMap<String, SecretSpec> = serde_yaml::from_str(
+      "
+      id: uuid
+      sortable: ulid
+      token: random
+      password: password
+      ssh: sshKey
+      ",
+    )
+    .unwrap();
+    let object: BTreeMap<String, SecretSpec> = serde_yaml::from_str(
+      "
+      id: { type: uuid }
+      sortable: { type: ulid }
+      token:
+        type: random
+      password:
+        type: password
+      ssh: { type: sshKey }
+      ",
+    )
+    .unwrap();
+
+    assert_eq!(shorthand, object);
+    for (name, spec) in &shorthand {
+      let (inputs, values) = (Inputs::default(), Values::default());
+      assert_eq!(
+        hash(spec, &inputs, &values),
+        hash(&object[name], &inputs, &values),
+        "{name}"
+      );
+    }
+  }
+
+  #[test]
+  fn object_form_round_trips() {
+    let spec: SecretSpec = serde_yaml::from_str("{ type: random, length: 48 }").unwrap();
+    let yaml = serde_yaml::to_string(&spec).unwrap();
+    let parsed: SecretSpec = serde_yaml::from_str(&yaml).unwrap();
+
+    let (inputs, values) = (Inputs::default(), Values::default());
+    assert_eq!(parsed, spec);
+    assert_eq!(hash(&parsed, &inputs, &values), hash(&spec, &inputs, &values));
+    let default: SecretSpec = serde_yaml::from_str("random").unwrap();
+    assert_ne!(hash(&default, &inputs, &values), hash(&spec, &inputs, &values));
+  }
+
+  #[test]
+  fn changing_a_default_regenerates_the_secrets_inheriting_it() {
+    let hashes = |length: usize| {
+      let auto_secret: AutoSecret = serde_json::from_value(serde_json::json!({
+        "apiVersion": "webstep.no/v1alpha1",
+        "kind": "AutoSecret",
+        "metadata": { "name": "app", "namespace": "default", "uid": "uid" },
+        "spec": {
+          "defaults": { "length": length },
+          "secrets": {
+            "inherited": "random",
+            "password": { "type": "password" },
+            "overridden": { "type": "random", "length": 16 },
+            "unrelated": "uuid",
+          },
+        },
+      }))
+      .unwrap();
+
+      let (inputs, values) = (Inputs::default(), Values::default());
+      let secrets = auto_secret.spec.secrets;
+      let hashes = secrets
+        .iter()
+        .map(|(name, spec)| (name.clone(), hash(spec, &inputs, &values)));
+      hashes.collect::<BTreeMap<_, _>>()
+    };
+
+    let (before, after) = (hashes(20), hashes(30));
+    let changed = before
+      .keys()
+      .filter(|name| before[*name] != after[*name])
+      .collect::<Vec<_>>();
+    assert_eq!(changed, ["inherited", "password"]);
+  }
+
+  #[tokio::test]
+  async fn secrets_are_regenerated_once_their_rotation_interval_passed() {
+    let inputs = Inputs::default();
+    let (rotating, fixed) = (
+      spec(serde_json::json!({ "type": "random", "rotateAfter": "1h" })),
+      spec("random".into()),
+    );
+    let mut secret = Secret::default();
+    secret.set_secret("rotating", &rotating, &inputs).await.unwrap();
+    secret.set_secret("fixed", &fixed, &inputs).await.unwrap();
+
+    let hour = Duration::from_secs(60 * 60);
+    let rotation = secret.rotation_in("rotating", &rotating).unwrap();
+    assert!(rotation > hour - Duration::from_secs(60) && rotation <= hour);
+    assert!(matches!(
+      secret.secret_status("rotating", &rotating, &inputs),
+      SecretStatus::Matches
+    ));
+
+    // keys without a rotation interval are never due, however old they are
+    set_generated(&mut secret, "fixed", 1000 * hour);
+    assert_eq!(secret.rotation_in("fixed", &fixed), None);
+    assert!(matches!(
+      secret.secret_status("fixed", &fixed, &inputs),
+      SecretStatus::Matches
+    ));
+
+    set_generated(&mut secret, "rotating", 2 * hour);
+    assert_eq!(secret.rotation_in("rotating", &rotating), Some(Duration::ZERO));
+    assert!(matches!(
+      secret.secret_status("rotating", &rotating, &inputs),
+      SecretStatus::Expired
+    ));
+
+    let before = secret.data.as_ref().unwrap()["rotating"].clone();
+    secret.set_secret("rotating", &rotating, &inputs).await.unwrap();
+    assert_ne!(secret.data.as_ref().unwrap()["rotating"], before);
+    assert!(matches!(
+      secret.secret_status("rotating", &rotating, &inputs),
+      SecretStatus::Matches
+    ));
+    assert!(secret.rotation_in("rotating", &rotating).unwrap() > hour - Duration::from_secs(60));
+  }
+
+  /// Applies the merge patch of the annotations in `patch` to `auto_secret`.
+  fn patch_annotations(auto_secret: &mut AutoSecret, patch: &serde_json::Value) {
+    let annotations = auto_secret.metadata.annotations.get_or_insert_with(Default::default);
+    for (name, value) in patch["metadata"]["annotations"].as_object().unwrap() {
+      match value.as_str() {
+        Some(value) => annotations.insert(name.clone(), value.to_owned()),
+        None => annotations.remove(name),
+      };
+    }
+  }
+
+  #[tokio::test]
+  async fn requested_rotations_regenerate_only_the_requested_secrets() {
+    for (request, expected) in [("a", &["a"][..]), ("all", &["a", "b", "c"]), (" b , unknown ", &["b"])] {
+      let mut auto_secret = auto_secret(serde_json::json!({ ROTATE_ANNOTATION: request }));
+      let (inputs, secrets) = (Inputs::default(), auto_secret.secrets());
+      let mut secret = Secret::default();
+      for (name, spec) in &secrets {
+        secret.set_secret(name, spec, &inputs).await.unwrap();
+      }
+
+      let before = secret.data.clone().unwrap();
+      let (rotate, _) = auto_secret.requested_rotations().unwrap();
+      for name in &rotate {
+        secret.force_rotation(name);
+        secret.set_secret(name, &secrets[name], &inputs).await.unwrap();
+      }
+
+      let data = secret.data.as_ref().unwrap();
+      let rotated = secrets.keys().filter(|name| data[*name] != before[*name]);
+      let rotated = rotated.map(String::as_str).collect::<BTreeSet<_>>();
+      assert_eq!(rotated, expected.iter().copied().collect(), "{request}");
+
+      // once the request is cleared, nothing is rotated or patched again
+      let patch = rotation_request_patch(&auto_secret, &[]).unwrap();
+      patch_annotations(&mut auto_secret, &patch);
+      assert_eq!(auto_secret.requested_rotations(), None);
+      assert_eq!(rotation_request_patch(&auto_secret, &[]), None);
+      let annotations = auto_secret.metadata.annotations.as_ref().unwrap();
+      assert!(annotations.contains_key(ROTATED_AT_ANNOTATION), "{request}");
+    }
+  }
+
+  #[tokio::test]
+  async fn overlapping_values_are_kept_until_the_overlap_ends() {
+    let (inputs, spec) = (
+      Inputs::default(),
+      spec(serde_json::json!({ "type": "random", "overlap": "1h" })),
+    );
+    let hour = Duration::from_secs(60 * 60);
+    let mut secret = Secret::default();
+
+    // active: only the current value exists
+    secret.set_secret("key", &spec, &inputs).await.unwrap();
+    let first = secret.data.as_ref().unwrap()["key"].clone();
+    assert!(!secret.data.as_ref().unwrap().contains_key("key.old"));
+    assert_eq!(secret.previous_expires_in("key", &spec), None);
+
+    // overlapping: the replaced value is kept next to the new one until the overlap ends
+    secret.force_rotation("key");
+    secret.set_secret("key", &spec, &inputs).await.unwrap();
+    let data = secret.data.as_ref().unwrap();
+    assert_ne!(data["key"], first);
+    assert_eq!(data["key.old"], first);
+    let expires = secret.previous_expires_in("key", &spec).unwrap();
+    assert!(expires > hour - Duration::from_secs(60) && expires <= hour);
+
+    secret.remove_expired_previous("key", &spec);
+    assert_eq!(secret.data.as_ref().unwrap()["key.old"], first);
+
+    // settled: once the overlap is over, the replaced value is removed
+    let kept = Utc::now() - chrono::Duration::hours(2);
+    let annotations = secret.metadata.annotations.as_mut().unwrap();
+    annotations.insert(previous_annotation_name("key.old"), timestamp(kept));
+    secret.remove_expired_previous("key", &spec);
+    secret.remove_expired_previous("key", &spec);
+    assert!(!secret.data.as_ref().unwrap().contains_key("key.old"));
+    assert!(!secret
+      .metadata
+      .annotations
+      .as_ref()
+      .unwrap()
+      .contains_key(&previous_annotation_name("key.old")));
+    assert_eq!(secret.previous_expires_in("key", &spec), None);
+  }
+
+  fn with_history(history: serde_json::Value) -> (AutoSecret, (HistorySpec, String, Vec<String>)) {
+    let mut resource = auto_secret(serde_json::json!({}));
+    resource.spec.history = Some(serde_json::from_value(history).unwrap());
+    let history = resource.history().unwrap().unwrap();
+    (resource, history)
+  }
+
+  fn data_secret(data: &[(&str, &[u8])]) -> Secret {
+    let data = data
+      .iter()
+      .map(|(key, value)| (key.to_string(), ByteString(value.to_vec())));
+    Secret {
+      data: Some(data.collect()),
+      ..Default::default()
+    }
+  }
+
+  fn days_ago(days: i64) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::days(days)
+  }
+
+  fn entries(secret: &Secret, key: &str) -> Vec<Vec<u8>> {
+    let data = secret.data.iter().flatten();
+    data
+      .filter(|(entry, _)| HistorySpec::parse_entry(entry).is_some_and(|(entry_key, _)| entry_key == key))
+      .map(|(_, value)| value.0.clone())
+      .collect()
+  }
+
+  #[test]
+  fn history_keeps_the_last_values_of_every_key() {
+    let (resource, history) = with_history(serde_json::json!({ "limit": 3, "include": ["a", "b"] }));
+    let existing = data_secret(&[
+      (&HistorySpec::entry("a", days_ago(3)), b"a1"),
+      (&HistorySpec::entry("a", days_ago(2)), b"a2"),
+      (&HistorySpec::entry("a", days_ago(1)), b"a3"),
+      (&HistorySpec::entry("b", days_ago(1)), b"b1"),
+    ]);
+    let secret = data_secret(&[("a", b"a4"), ("b", b"b1"), ("c", b"c1")]);
+
+    let (history_secret, pruned) = resource.history_secret(&secret, Some(&existing), &history).unwrap();
+    assert_eq!(history_secret.metadata.name.as_deref(), Some("app-history"));
+    assert_eq!(entries(&history_secret, "a"), [b"a2", b"a3", b"a4"]);
+    assert_eq!(entries(&history_secret, "b"), [b"b1"]);
+    assert!(entries(&history_secret, "c").is_empty());
+    assert!(pruned.is_empty());
+  }
+
+  #[test]
+  fn history_is_pruned_oldest_first_to_fit_in_a_secret() {
+    let (resource, history) = with_history(serde_json::json!({ "limit": 5, "include": ["a", "b"] }));
+    let value = |byte: u8| vec![byte; 300 * 1024];
+    let (a1, b1, a2) = (value(1), value(2), value(3));
+    let (oldest, older) = (
+      HistorySpec::entry("a", days_ago(3)),
+      HistorySpec::entry("b", days_ago(2)),
+    );
+    let existing = data_secret(&[
+      (&oldest, &a1),
+      (&older, &b1),
+      (&HistorySpec::entry("a", days_ago(1)), &a2),
+    ]);
+    let (a3, b2) = (value(4), value(5));
+    let secret = data_secret(&[("a", &a3), ("b", &b2)]);
+
+    let (history_secret, pruned) = resource.history_secret(&secret, Some(&existing), &history).unwrap();
+    assert_eq!(pruned, [oldest, older]);
+    assert_eq!(entries(&history_secret, "a"), [a2, a3]);
+    assert_eq!(entries(&history_secret, "b"), [b2]);
+    assert!(history_secret.check_size().is_ok());
+  }
+
+  /// Applies the merge `patch` to `value` the way the api server does.
+  fn apply_merge_patch(value: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch = match patch {
+      serde_json::Value::Object(patch) => patch,
+      patch => return *value = patch.clone(),
+    };
+
+    if !value.is_object() {
+      *value = serde_json::json!({});
+    }
+
+    let object = value.as_object_mut().unwrap();
+    for (key, patch) in patch {
+      match patch {
+        serde_json::Value::Null => drop(object.remove(key)),
+        patch => apply_merge_patch(object.entry(key.clone()).or_insert(serde_json::Value::Null), patch),
+      }
+    }
+  }
+
+  fn status(status: serde_json::Value) -> crate::AutoSecretStatus {
+    serde_json::from_value(status).unwrap()
+  }
+
+  #[test]
+  fn status_patch_clears_the_keys_that_recovered() {
+    let existing = status(serde_json::json!({
+      "keyCount": 3,
+      "failedKeys": [{ "name": "b", "reason": "GenerateFailed", "message": "failed" }],
+    }));
+    let recovered = status(serde_json::json!({ "keyCount": 3 }));
+
+    let patch = status_patch(Some(&existing), &recovered);
+    assert_eq!(patch, serde_json::json!({ "failedKeys": null }));
+
+    let mut patched = serde_json::json!(existing);
+    apply_merge_patch(&mut patched, &patch);
+    assert_eq!(patched, serde_json::json!(recovered));
+  }
+
+  #[test]
+  fn status_patch_only_changes_what_changed() {
+    let existing = status(serde_json::json!({ "keyCount": 3, "secretName": "app" }));
+    assert_eq!(status_patch(Some(&existing), &existing), serde_json::json!({}));
+
+    let status = status(serde_json::json!({ "keyCount": 2, "secretName": "app" }));
+    assert_eq!(
+      status_patch(Some(&existing), &status),
+      serde_json::json!({ "keyCount": 2 })
+    );
+    assert_eq!(status_patch(None, &status), serde_json::json!(status));
+  }
+
+  #[tokio::test]
+  async fn generation_times_are_stored_in_whole_seconds() {
+    let spec = spec(serde_json::json!({ "type": "random", "rotateAfter": "1h", "keepPrevious": true }));
+    let mut secret = Secret::default();
+    secret.set_secret("key", &spec, &Inputs::default()).await.unwrap();
+    secret.force_rotation("key");
+    secret.set_secret("key", &spec, &Inputs::default()).await.unwrap();
+
+    let annotations = secret.metadata.annotations.clone().unwrap();
+    let times = annotations.iter().filter(|(annotation, _)| {
+      annotation.starts_with(GENERATED_ANNOTATION_PREFIX) || annotation.starts_with(PREVIOUS_ANNOTATION_PREFIX)
+    });
+    let times = times.map(|(_, time)| time).collect::<Vec<_>>();
+    assert_eq!(times.len(), 2, "{annotations:?}");
+    for time in times {
+      let parsed = DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc);
+      assert_eq!(time, &timestamp(parsed));
+    }
+
+    let keys = secret.key_statuses(&[("key".to_owned(), spec)]);
+    let generated = keys["key"].last_generated_time.as_ref().unwrap();
+    assert_eq!(
+      Some(&timestamp(generated.0)),
+      annotations.get(&generated_annotation_name("key"))
+    );
+  }
+
+  /// Whether the `keys` of the status are the ones recorded in the annotations of the `secret`.
+  fn keys_match_annotations(keys: &BTreeMap<String, crate::KeyStatus>, secret: &Secret) -> bool {
+    let annotations = secret.metadata.annotations.clone().unwrap_or_default();
+    let entries = annotations
+      .keys()
+      .filter_map(|annotation| annotation.strip_prefix(ANNOTATION_PREFIX))
+      .collect::<BTreeSet<_>>();
+
+    entries == keys.keys().map(String::as_str).collect()
+      && keys.iter().all(|(name, key)| {
+        let generated = key.last_generated_time.as_ref().map(|time| timestamp(time.0));
+        annotations.get(&annotation_name(name)) == Some(&key.hash)
+          && annotations.get(&generated_annotation_name(name)) == generated.as_ref()
+      })
+  }
+
+  #[tokio::test]
+  async fn status_keys_follow_the_secret_when_keys_are_removed() {
+    let resource = auto_secret(serde_json::json!({}));
+    let mut secret = Secret::default();
+    for (name, spec) in resource.ordered_secrets().unwrap() {
+      secret.set_secret(&name, &spec, &Inputs::default()).await.unwrap();
+    }
+
+    let keys = secret.key_statuses(&resource.ordered_secrets().unwrap());
+    assert!(keys_match_annotations(&keys, &secret));
+    let existing = crate::AutoSecretStatus {
+      keys,
+      ..Default::default()
+    };
+
+    // b is removed from the spec, and the rest of the secrets are left as they are
+    let mut resource = resource;
+    resource.spec.secrets.remove("b");
+    crate::remove_stale_keys(&resource, &mut secret, &resource.secrets());
+    let keys = secret.key_statuses(&resource.ordered_secrets().unwrap());
+    assert!(keys_match_annotations(&keys, &secret));
+    assert_eq!(keys.keys().collect::<Vec<_>>(), ["a", "c"]);
+    let status = crate::AutoSecretStatus {
+      keys,
+      ..Default::default()
+    };
+
+    let patch = status_patch(Some(&existing), &status);
+    assert_eq!(patch, serde_json::json!({ "keys": { "b": null } }));
+    let mut patched = serde_json::json!(existing);
+    apply_merge_patch(&mut patched, &patch);
+    assert_eq!(patched, serde_json::json!(status));
+
+    // without any keys left, the keys are removed from the status rather than left out of the patch
+    let empty = crate::AutoSecretStatus::default();
+    assert_eq!(status_patch(Some(&status), &empty), serde_json::json!({ "keys": null }));
+  }
+
+  #[tokio::test]
+  async fn secrets_without_a_rotation_interval_never_rotate() {
+    let inputs = Inputs::default();
+    for spec in [
+      spec("random".into()),
+      spec(serde_json::json!({ "type": "password", "minAge": "1h" })),
+    ] {
+      let mut secret = Secret::default();
+      secret.set_secret("key", &spec, &inputs).await.unwrap();
+      let value = secret.data.clone().unwrap();
+
+      // however long ago the value was generated, it isn't due
+      let annotations = secret.metadata.annotations.get_or_insert_with(Default::default);
+      annotations.insert(generated_annotation_name("key"), "2001-01-01T00:00:00Z".to_owned());
+      assert_eq!(secret.rotation_in("key", &spec), None);
+      assert!(matches!(
+        secret.secret_status("key", &spec, &inputs),
+        SecretStatus::Matches
+      ));
+
+      let secrets = [("key".to_owned(), spec)];
+      let generated = crate::generate_secrets(&mut secret, &secrets, &inputs, &BTreeSet::new(), Duration::ZERO).await;
+      assert!(generated.generated.is_empty() && generated.cooldowns.is_empty());
+      assert_eq!(secret.data.unwrap(), value);
+    }
+  }
+
+  #[tokio::test]
+  async fn env_file_is_rendered_with_a_hex_hash() {
+    let resource = auto_secret(serde_json::json!({}));
+    let mut secret = Secret::default();
+    for (name, spec) in resource.ordered_secrets().unwrap() {
+      secret.set_secret(&name, &spec, &Inputs::default()).await.unwrap();
+    }
+
+    let env_file = serde_json::from_value(serde_json::json!({})).unwrap();
+    secret
+      .set_env_file(&env_file, &["a".to_owned(), "c".to_owned()])
+      .unwrap();
+    let data = secret.data.clone().unwrap();
+    let file = String::from_utf8(data[".env"].0.clone()).unwrap();
+    let c = std::str::from_utf8(&data["c"].0).unwrap();
+    assert!(
+      file.starts_with("A=") && file.ends_with(&format!("\nC={c}\n")),
+      "{file}"
+    );
+
+    // the bundles are hashed the same way as the secrets
+    let annotations = secret.metadata.annotations.clone().unwrap();
+    let hashes = [annotation_name(".env"), annotation_name("a")].map(|name| annotations[&name].clone());
+    assert_eq!(hashes[0].len(), 16);
+    assert!(hashes[0].bytes().all(|b| b.is_ascii_hexdigit()), "{}", hashes[0]);
+    assert!(hashes[1].split('.').all(|hash| hash.len() == 16));
+  }
+
+  fn with_secrets(secrets: serde_json::Value) -> AutoSecret {
+    let mut resource = auto_secret(serde_json::json!({}));
+    resource.spec.secrets = serde_json::from_value(secrets).unwrap();
+    resource
+  }
+
+  #[test]
+  fn certificates_writing_the_same_key_are_rejected() {
+    let resource = with_secrets(serde_json::json!({
+      "server": { "type": "signedCertificate", "issuer": "ca" },
+      "client": { "type": "signedCertificate", "issuer": "ca", "certificateKeyName": "client.crt",
+        "certificatePrivateKeyName": "client.key" },
+    }));
+    let error = resource.ordered_secrets().unwrap_err();
+    assert!(
+      matches!(&error, ControllerError::ConflictingOutputs(conflicts) if conflicts == "ca.crt by client and server"),
+      "{error}"
+    );
+
+    let resource = with_secrets(serde_json::json!({
+      "server": { "type": "signedCertificate", "issuer": "ca" },
+      "client": { "type": "signedCertificate", "issuer": "ca", "certificateKeyName": "client.crt",
+        "certificatePrivateKeyName": "client.key", "caCertificateKeyName": "client-ca.crt" },
+    }));
+    assert_eq!(resource.ordered_secrets().unwrap().len(), 2);
+  }
+
+  #[tokio::test]
+  async fn retained_secrets_are_released_from_the_autosecret() {
+    let mut resource = auto_secret(serde_json::json!({}));
+    assert!(!resource.retains_secrets());
+    resource.spec.deletion_policy = DeletionPolicy::Retain;
+    assert!(resource.retains_secrets());
+
+    // the secret is owned by the autosecret until it's deleted, when the finalizer releases it
+    let mut owners = resource.owner_references().unwrap();
+    assert_eq!(owners[0].uid, "uid");
+    owners.push(OwnerReference {
+      uid: "other".into(),
+      ..owners[0].clone()
+    });
+    let mut secret = Secret::default();
+    secret.metadata.owner_references = Some(owners);
+    secret.metadata.annotations = Some(BTreeMap::from([
+      (OWNER_ANNOTATION.to_owned(), "app".to_owned()),
+      ("team".to_owned(), "payments".to_owned()),
+    ]));
+    for (name, spec) in resource.ordered_secrets().unwrap() {
+      secret.set_secret(&name, &spec, &Inputs::default()).await.unwrap();
+    }
+
+    let patch = release_patch(&secret, &resource);
+    let mut released = serde_json::json!(secret);
+    apply_merge_patch(&mut released, &patch);
+    let released = serde_json::from_value::<Secret>(released).unwrap();
+    let owners = released.metadata.owner_references.unwrap();
+    assert_eq!(
+      owners.iter().map(|owner| owner.uid.as_str()).collect::<Vec<_>>(),
+      ["other"]
+    );
+    assert_eq!(
+      released.metadata.annotations.unwrap(),
+      BTreeMap::from([("team".to_owned(), "payments".to_owned())])
+    );
+    assert_eq!(released.data, secret.data);
+  }
+
+  fn api_error(code: u16, message: &str) -> ControllerError {
+    ControllerError::SecretApplyFailed(kube::Error::Api(kube::error::ErrorResponse {
+      status: "Failure".into(),
+      message: message.into(),
+      reason: "Invalid".into(),
+      code,
+    }))
+  }
+
+  #[test]
+  fn updates_of_immutable_secrets_are_recognized() {
+    let immutable = api_error(
+      422,
+      "Secret \"app\" is invalid: data: Forbidden: field is immutable when `immutable` is set",
+    );
+    assert!(immutable.is_immutable_field());
+    assert!(!api_error(422, "Secret \"app\" is invalid: metadata.name: Invalid value").is_immutable_field());
+    assert!(!api_error(409, "the object has been modified").is_immutable_field());
+  }
+
+  #[test]
+  fn every_key_written_twice_is_reported() {
+    // a literal name taking a key of a generator, and a generator taking a companion key of another secret
+    let resource = with_secrets(serde_json::json!({
+      "tls.crt": "uuid",
+      "server": { "type": "signedCertificate", "issuer": "ca" },
+      "signing": "ed25519KeyPair",
+      "signing.pub": "random",
+      "token": { "type": "password", "bcrypt": {} },
+      "token.bcrypt": "uuid",
+    }));
+    let error = resource.ordered_secrets().unwrap_err();
+    let conflicts = "signing.pub by signing and signing.pub, tls.crt by server and tls.crt, token.bcrypt by token and \
+                     token.bcrypt";
+    assert!(
+      matches!(&error, ControllerError::ConflictingOutputs(c) if c == conflicts),
+      "{error}"
+    );
+    assert_eq!(error.reason(), "InvalidSpec");
+    assert!(error.is_permanent());
+  }
+
+  #[tokio::test]
+  async fn expiring_certificates_are_warned_about_once() {
+    let threshold = Duration::from_secs(48 * 60 * 60);
+    let mut secret = Secret::default();
+    let ca = spec(serde_json::json!({ "type": "caCertificate", "validityDays": 1 }));
+    secret.set_secret("ca", &ca, &Inputs::default()).await.unwrap();
+    let (expires, unrotated) = secret.expiry("ca", &ca).unwrap();
+    assert!(unrotated);
+
+    // far from expiring, it isn't warned about until it's within the threshold
+    assert_eq!(secret.expiry_warning("ca", &ca, Duration::from_secs(60 * 60)), None);
+    let warnings = (0..5)
+      .filter_map(|_| secret.expiry_warning("ca", &ca, threshold))
+      .collect::<Vec<_>>();
+    assert_eq!(warnings, [expires]);
+    assert_eq!(secret.expiry_warning_in("ca", &ca, threshold), None);
+
+    // a reissued certificate expires at another time, which is warned about again
+    let reissued = spec(serde_json::json!({ "type": "caCertificate", "validityDays": 1, "revision": 1 }));
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    secret.set_secret("ca", &reissued, &Inputs::default()).await.unwrap();
+    let (reissued_expires, _) = secret.expiry("ca", &reissued).unwrap();
+    assert!(reissued_expires > expires);
+    assert_eq!(
+      secret.expiry_warning("ca", &reissued, threshold),
+      Some(reissued_expires)
+    );
+    assert_eq!(secret.expiry_warning("ca", &reissued, threshold), None);
+
+    // values that are rotated when they expire are replaced in time
+    let password = spec(serde_json::json!({ "type": "password", "rotateAfter": "1h" }));
+    secret
+      .set_secret("password", &password, &Inputs::default())
+      .await
+      .unwrap();
+    assert!(matches!(secret.expiry("password", &password), Some((_, false))));
+    assert_eq!(secret.expiry_warning("password", &password, threshold), None);
+  }
 }
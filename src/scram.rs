@@ -0,0 +1,96 @@
+//! SCRAM-SHA-256 verifiers (RFC 5802, RFC 7677), in the format postgres stores them in `pg_authid`.
+
+use openssl::{
+  error::ErrorStack,
+  hash::{hash, MessageDigest},
+  pkcs5::pbkdf2_hmac,
+  pkey::PKey,
+  sign::Signer,
+};
+
+const PREFIX: &str = "SCRAM-SHA-256$";
+
+/// Length of the random salts of new verifiers, matching the salts generated by postgres.
+pub const SALT_LENGTH: usize = 16;
+
+/// Computes the verifier of `password` for `salt`, as `SCRAM-SHA-256$<iterations>:<salt>$<stored key>:<server key>`.
+///
+/// postgres normalizes passwords with SASLprep before hashing them, which leaves ascii passwords unchanged.
+pub fn verifier(password: &[u8], salt: &[u8], iterations: u32) -> Result<String, ErrorStack> {
+  let mut salted_password = [0u8; 32];
+  pbkdf2_hmac(
+    password,
+    salt,
+    iterations as usize,
+    MessageDigest::sha256(),
+    &mut salted_password,
+  )?;
+
+  let client_key = hmac(&salted_password, b"Client Key")?;
+  let stored_key = hash(MessageDigest::sha256(), &client_key)?;
+  let server_key = hmac(&salted_password, b"Server Key")?;
+  Ok(format!(
+    "{PREFIX}{iterations}:{}${}:{}",
+    base64::encode(salt),
+    base64::encode(stored_key),
+    base64::encode(server_key),
+  ))
+}
+
+/// Returns the salt of `verifier`, if it is a SCRAM-SHA-256 verifier.
+pub fn salt(verifier: &str) -> Option<Vec<u8>> {
+  let (_, salt) = verifier.strip_prefix(PREFIX)?.split_once('$')?.0.split_once(':')?;
+  base64::decode(salt).ok()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+  let key = PKey::hmac(key)?;
+  let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+  signer.update(data)?;
+  signer.sign_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Checks that a client proof for `auth_message` verifies against `verifier` the way a server does, and returns the
+  /// signature the server answers with.
+  fn server_signature(verifier: &str, auth_message: &[u8], client_proof: &[u8]) -> Option<Vec<u8>> {
+    let (_, keys) = verifier.strip_prefix(PREFIX)?.split_once('$')?;
+    let (stored_key, server_key) = keys.split_once(':')?;
+    let (stored_key, server_key) = (base64::decode(stored_key).ok()?, base64::decode(server_key).ok()?);
+
+    let client_signature = hmac(&stored_key, auth_message).unwrap();
+    let client_key = client_proof
+      .iter()
+      .zip(client_signature)
+      .map(|(a, b)| a ^ b)
+      .collect::<Vec<_>>();
+    let verified = *hash(MessageDigest::sha256(), &client_key).unwrap() == *stored_key;
+    verified.then(|| hmac(&server_key, auth_message).unwrap())
+  }
+
+  #[test]
+  fn rfc_7677_exchange_verifies() {
+    let salt = base64::decode("W22ZaJ0SNY7soEsUEjb6gQ==").unwrap();
+    let pencil = verifier(b"pencil", &salt, 4096).unwrap();
+    assert!(pencil.starts_with("SCRAM-SHA-256$4096:W22ZaJ0SNY7soEsUEjb6gQ==$"));
+    assert_eq!(super::salt(&pencil), Some(salt.clone()));
+
+    let auth_message = concat!(
+      "n=user,r=rOprNGfwEbeRWgbNEkqO,",
+      "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096,",
+      "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+    );
+    let client_proof = base64::decode("dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ=").unwrap();
+    let signature = server_signature(&pencil, auth_message.as_bytes(), &client_proof);
+    assert_eq!(
+      signature.map(base64::encode).as_deref(),
+      Some("6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+    );
+
+    let wrong = verifier(b"pencils", &salt, 4096).unwrap();
+    assert_eq!(server_signature(&wrong, auth_message.as_bytes(), &client_proof), None);
+  }
+}
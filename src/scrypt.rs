@@ -0,0 +1,72 @@
+//! scrypt password hashing (RFC 7914), producing PHC strings.
+
+use openssl::error::ErrorStack;
+use rand::{rngs::OsRng, Rng};
+
+const HASH_LENGTH: usize = 32;
+
+/// Hashes `password` with a random salt, producing a `$scrypt$` PHC string.
+///
+/// `n` is the cost parameter, and must be a power of two greater than 1.
+pub fn hash(password: &[u8], n: u64, r: u32, p: u32) -> Result<String, ErrorStack> {
+  let salt: [u8; 16] = OsRng.gen();
+  hash_with_salt(password, &salt, n, r, p)
+}
+
+fn hash_with_salt(password: &[u8], salt: &[u8], n: u64, r: u32, p: u32) -> Result<String, ErrorStack> {
+  let mut hash = [0u8; HASH_LENGTH];
+
+  // openssl refuses to use more than 32 MiB unless told otherwise, which the default parameters already need
+  let max_memory = 128 * (r as u64) * (n + p as u64 + 2);
+  openssl::pkcs5::scrypt(password, salt, n, r as u64, p as u64, max_memory, &mut hash)?;
+
+  Ok(format!(
+    "$scrypt$ln={},r={r},p={p}${}${}",
+    n.trailing_zeros(),
+    base64::encode_config(salt, base64::STANDARD_NO_PAD),
+    base64::encode_config(hash, base64::STANDARD_NO_PAD),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn rfc_7914_vectors() {
+    // the hashes are the first 32 bytes of the 64 byte keys of the RFC, as scrypt ends with PBKDF2
+    let vectors = [
+      (
+        &b"password"[..],
+        &b"NaCl"[..],
+        1024,
+        8,
+        16,
+        "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162",
+      ),
+      (
+        b"pleaseletmein",
+        b"SodiumChloride",
+        16384,
+        8,
+        1,
+        "7023bdcb3afd7348461c06cd81fd38ebfda8fbba904f8e3ea9b543f6545da1f2",
+      ),
+    ];
+
+    for (password, salt, n, r, p, expected) in vectors {
+      let hashed = hash_with_salt(password, salt, n, r, p).unwrap();
+      let expected = base64::encode_config(hex::decode(expected).unwrap(), base64::STANDARD_NO_PAD);
+      let salt = base64::encode_config(salt, base64::STANDARD_NO_PAD);
+      let ln = n.trailing_zeros();
+      assert_eq!(hashed, format!("$scrypt$ln={ln},r={r},p={p}${salt}${expected}"));
+    }
+  }
+
+  #[test]
+  fn random_hashes_use_a_new_salt() {
+    let hashed = hash(b"password", 16, 1, 1).unwrap();
+    assert!(hashed.starts_with("$scrypt$ln=4,r=1,p=1$"));
+    assert_ne!(hash(b"password", 16, 1, 1).unwrap(), hashed);
+  }
+}
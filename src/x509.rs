@@ -0,0 +1,157 @@
+//! X.509 certificate issuance.
+
+use openssl::{
+  asn1::Asn1Time,
+  bn::{BigNum, MsbOption},
+  error::ErrorStack,
+  hash::MessageDigest,
+  pkey::{PKey, PKeyRef, Private},
+  x509::{
+    extension::{
+      AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+      SubjectKeyIdentifier,
+    },
+    X509Builder, X509Name, X509NameBuilder, X509NameRef, X509Ref, X509,
+  },
+};
+use std::{
+  net::IpAddr,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Distinguished name of a certificate.
+pub struct Subject<'a> {
+  pub common_name: &'a str,
+  pub organization: Option<&'a str>,
+}
+
+impl Subject<'_> {
+  fn to_name(&self) -> Result<X509Name, ErrorStack> {
+    let mut name = X509NameBuilder::new()?;
+    if let Some(organization) = self.organization {
+      name.append_entry_by_text("O", organization)?;
+    }
+
+    name.append_entry_by_text("CN", self.common_name)?;
+    Ok(name.build())
+  }
+}
+
+/// Creates a self-signed CA certificate for `key`, valid from now for `days` days.
+pub fn ca_certificate(
+  key: &PKey<Private>,
+  subject: &Subject,
+  days: u32,
+  path_length: Option<u32>,
+) -> Result<X509, ErrorStack> {
+  let name = subject.to_name()?;
+  let mut builder = builder(key, &name, &name, days)?;
+
+  let mut basic_constraints = BasicConstraints::new();
+  basic_constraints.critical().ca();
+  if let Some(path_length) = path_length {
+    basic_constraints.pathlen(path_length);
+  }
+
+  builder.append_extension(basic_constraints.build()?)?;
+  builder.append_extension(KeyUsage::new().critical().key_cert_sign().crl_sign().build()?)?;
+
+  let subject_key_identifier = SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+  builder.append_extension(subject_key_identifier)?;
+  let authority_key_identifier = AuthorityKeyIdentifier::new()
+    .keyid(true)
+    .build(&builder.x509v3_context(None, None))?;
+  builder.append_extension(authority_key_identifier)?;
+
+  builder.sign(key, digest(key))?;
+  Ok(builder.build())
+}
+
+/// Creates a certificate for `key` signed by `issuer`, usable for both tls servers and clients, valid from now for
+/// `days` days.
+pub fn leaf_certificate(
+  key: &PKey<Private>,
+  subject: &Subject,
+  days: u32,
+  dns_names: &[String],
+  ip_addresses: &[IpAddr],
+  issuer: &X509Ref,
+  issuer_key: &PKey<Private>,
+) -> Result<X509, ErrorStack> {
+  let name = subject.to_name()?;
+  let mut builder = builder(key, &name, issuer.subject_name(), days)?;
+  builder.append_extension(BasicConstraints::new().critical().build()?)?;
+
+  let mut key_usage = KeyUsage::new();
+  key_usage.critical().digital_signature();
+  if key.id() == openssl::pkey::Id::RSA {
+    key_usage.key_encipherment();
+  }
+
+  builder.append_extension(key_usage.build()?)?;
+  builder.append_extension(ExtendedKeyUsage::new().server_auth().client_auth().build()?)?;
+
+  if !dns_names.is_empty() || !ip_addresses.is_empty() {
+    let mut alt_names = SubjectAlternativeName::new();
+    for dns_name in dns_names {
+      alt_names.dns(dns_name);
+    }
+
+    for ip_address in ip_addresses {
+      alt_names.ip(&ip_address.to_string());
+    }
+
+    let alt_names = alt_names.build(&builder.x509v3_context(Some(issuer), None))?;
+    builder.append_extension(alt_names)?;
+  }
+
+  let subject_key_identifier = SubjectKeyIdentifier::new().build(&builder.x509v3_context(Some(issuer), None))?;
+  builder.append_extension(subject_key_identifier)?;
+  let authority_key_identifier = AuthorityKeyIdentifier::new()
+    .keyid(true)
+    .build(&builder.x509v3_context(Some(issuer), None))?;
+  builder.append_extension(authority_key_identifier)?;
+
+  builder.sign(issuer_key, digest(issuer_key))?;
+  Ok(builder.build())
+}
+
+/// Time the first certificate of the PEM encoded `chain` expires, if it can be read.
+pub fn not_after(chain: &[u8]) -> Option<SystemTime> {
+  let certificate = X509::from_pem(chain).ok()?;
+  let since_epoch = Asn1Time::from_unix(0).ok()?.diff(certificate.not_after()).ok()?;
+  let seconds = i64::from(since_epoch.days) * 24 * 60 * 60 + i64::from(since_epoch.secs);
+  UNIX_EPOCH.checked_add(Duration::from_secs(seconds.try_into().ok()?))
+}
+
+fn builder(
+  key: &PKeyRef<Private>,
+  subject: &X509NameRef,
+  issuer: &X509NameRef,
+  days: u32,
+) -> Result<X509Builder, ErrorStack> {
+  let mut builder = X509Builder::new()?;
+  builder.set_version(2)?;
+
+  // random positive 127 bit serial number, well within the 20 octets allowed by RFC 5280
+  let mut serial = BigNum::new()?;
+  serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+  let serial = serial.to_asn1_integer()?;
+  builder.set_serial_number(&serial)?;
+
+  builder.set_subject_name(subject)?;
+  builder.set_issuer_name(issuer)?;
+  let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(days)?);
+  builder.set_not_before(&not_before)?;
+  builder.set_not_after(&not_after)?;
+  builder.set_pubkey(key)?;
+  Ok(builder)
+}
+
+fn digest(key: &PKeyRef<Private>) -> MessageDigest {
+  // ed25519 signatures don't use a separate digest
+  match key.id() {
+    openssl::pkey::Id::ED25519 => MessageDigest::null(),
+    _ => MessageDigest::sha256(),
+  }
+}